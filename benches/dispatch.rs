@@ -0,0 +1,43 @@
+// Size & dispatch speed of a long arithmetic chunk,
+// as compiled and optimized, fastest of `bench::RUNS`.
+//
+//   cargo bench --bench dispatch
+
+use rlox::bench::time;
+use rlox::chunk::*;
+use rlox::optimize::*;
+
+const OPS: usize = 100_000;
+
+fn main() {
+    // 1 + 1 + 1 ...
+    let mut chk = Chunk::new();
+    chk.write_constant(1.0, 1);
+    for i in 0..OPS {
+        chk.write_constant(1.0, i);
+        chk.write(Op::Add, i);
+    }
+    chk.write(Op::Return, OPS);
+
+    run("-O0", &chk);
+    run("-O2", &optimize(&chk, MAX_LEVEL));
+}
+
+fn run(name: &str, chk: &Chunk) {
+    let mut ops = 0;
+    let mut offset = 0;
+    while let Some(ins) = chk.decode(offset) {
+        offset += ins.size();
+        ops += 1;
+    }
+
+    let (elapsed, _) = time(chk);
+    println!(
+        "{}: {} ops in {} bytes, {:?}, {:.2} ns/op",
+        name,
+        ops,
+        chk.count,
+        elapsed,
+        elapsed.as_nanos() as f64 / ops as f64
+    );
+}
@@ -0,0 +1,182 @@
+// Optional syntax tree for tooling (linters, formatters, ...).
+//
+// The bytecode compiler doesn't go through it, it still parses
+// and emits code in a single pass, see `compiler.rs`. Both accept
+// the same grammar, the compiler rejects what the VM can't run yet.
+
+mod parser;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+
+/// Parse a whole program into a syntax tree.
+///
+/// All syntax errors are collected, the parser recovers at
+/// statement boundaries like the bytecode compiler.
+pub fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    parser::Parser::new(source).program()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub decls: Vec<Decl>,
+    // expression the program ends in without `;`, its value
+    pub value: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/* Declarations */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl {
+    Class(ClassDecl),
+    Fun(Function),
+    Var(VarDecl),
+    Stmt(Stmt),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDecl {
+    pub name: Ident,
+    pub superclass: Option<Ident>,
+    pub methods: Vec<Function>,
+    pub span: Span,
+}
+
+// also used for methods
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    pub body: Vec<Decl>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDecl {
+    pub name: Ident,
+    pub init: Option<Expr>,
+    pub span: Span,
+}
+
+impl Decl {
+    pub fn span(&self) -> Span {
+        match self {
+            Decl::Class(class) => class.span,
+            Decl::Fun(fun) => fun.span,
+            Decl::Var(var) => var.span,
+            Decl::Stmt(stmt) => stmt.span,
+        }
+    }
+}
+
+/* Statements */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Expr(Expr),
+    Print(Expr),
+    Block(Vec<Decl>),
+    If {
+        cond: Expr,
+        then: Box<Stmt>,
+        els: Option<Box<Stmt>>,
+    },
+    While {
+        cond: Expr,
+        body: Box<Stmt>,
+    },
+    // kept as is (not desugared) so the tree maps back to source
+    For {
+        init: Option<Box<Decl>>,
+        cond: Option<Expr>,
+        incr: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    Return(Option<Expr>),
+}
+
+/* Expressions */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Grouping(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Logical {
+        op: LogicalOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Variable(Ident),
+    Assign {
+        name: Ident,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Ident,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Ident,
+        value: Box<Expr>,
+    },
+    This,
+    Super(Ident),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
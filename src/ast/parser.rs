@@ -0,0 +1,639 @@
+// Recursive descent parser building the syntax tree,
+// it follows the grammar in the book (appendix I):
+//
+//   program     -> declaration* expression? EOF ;
+//   declaration -> classDecl | funDecl | varDecl | statement ;
+//   statement   -> exprStmt | forStmt | ifStmt | printStmt
+//                | returnStmt | whileStmt | block ;
+//
+// except a program may end in an expression without `;`, which
+// is its value, e.g. `1 + 2` in the REPL
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::*;
+
+const MAX_ARGS: usize = 255;
+
+pub struct Parser<'src> {
+    scanner: Scanner<'src>,
+    curr: Token<'src>,
+    prev: Token<'src>,
+    errors: Vec<Diagnostic>,
+    panic: bool,
+}
+
+impl<'src> Parser<'src> {
+    pub fn new(source: &'src str) -> Parser<'src> {
+        let mut scanner = Scanner::new(source);
+        let curr = scanner.scan_token();
+        let prev = curr.clone();
+
+        let mut parser = Parser {
+            scanner,
+            curr,
+            prev,
+            errors: Vec::new(),
+            panic: false,
+        };
+        parser.skip_lexic_errors();
+        parser
+    }
+
+    pub fn program(mut self) -> Result<Program, Vec<Diagnostic>> {
+        let mut decls = Vec::new();
+        let mut value = None;
+
+        while !self.check(TokenType::Eof) {
+            if self.starts_declaration() {
+                if let Some(decl) = self.declaration() {
+                    decls.push(decl);
+                }
+                continue;
+            }
+            match self.expr_stmt() {
+                Some(Ok(stmt)) => decls.push(Decl::Stmt(stmt)),
+                Some(Err(expr)) => value = Some(expr),
+                None => self.synchronize(),
+            }
+        }
+        if self.errors.is_empty() {
+            Ok(Program { decls, value })
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /* Tokens */
+
+    fn advance(&mut self) {
+        self.prev = std::mem::replace(&mut self.curr, self.scanner.scan_token());
+        self.skip_lexic_errors();
+    }
+
+    // report and drop error tokens, parser never sees them
+    fn skip_lexic_errors(&mut self) {
+        while let TokenType::Error = self.curr.tt {
+            let msg = self.curr.value.to_string();
+            self.error_at_curr(&msg);
+            self.curr = self.scanner.scan_token();
+        }
+    }
+
+    fn check(&self, tt: TokenType) -> bool {
+        self.curr.tt == tt
+    }
+
+    fn matches(&mut self, tt: TokenType) -> bool {
+        if !self.check(tt) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    fn consume(&mut self, tt: TokenType, msg: &str) -> Option<()> {
+        if self.check(tt) {
+            self.advance();
+            return Some(());
+        }
+        self.error_at_curr(msg);
+        None
+    }
+
+    fn ident(&mut self, msg: &str) -> Option<Ident> {
+        self.consume(TokenType::Identifier, msg)?;
+        Some(Ident {
+            name: self.prev.value.to_string(),
+            span: self.prev.span,
+        })
+    }
+
+    /* Errors */
+
+    fn error_at_curr(&mut self, msg: &str) {
+        if self.panic {
+            return;
+        }
+        self.panic = true;
+        self.errors.push(Diagnostic::at(&self.curr, msg));
+    }
+
+    fn error_at_prev(&mut self, msg: &str) {
+        if self.panic {
+            return;
+        }
+        self.panic = true;
+        self.errors.push(Diagnostic::at(&self.prev, msg));
+    }
+
+    // skip tokens until a statement boundary
+    fn synchronize(&mut self) {
+        self.panic = false;
+
+        while !self.check(TokenType::Eof) {
+            if self.prev.tt == TokenType::Semicolon {
+                return;
+            }
+            match self.curr.tt {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    /* Declarations */
+
+    // starts with a keyword, the rest are expression statements
+    fn starts_declaration(&self) -> bool {
+        matches!(
+            self.curr.tt,
+            TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::Print
+                | TokenType::LeftBrace
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Return
+        )
+    }
+
+    fn declaration(&mut self) -> Option<Decl> {
+        let decl = if self.matches(TokenType::Class) {
+            self.class_decl()
+        } else if self.matches(TokenType::Fun) {
+            self.function("Expect function name.").map(Decl::Fun)
+        } else if self.matches(TokenType::Var) {
+            self.var_decl()
+        } else {
+            self.statement().map(Decl::Stmt)
+        };
+
+        if decl.is_none() {
+            self.synchronize();
+        }
+        decl
+    }
+
+    fn class_decl(&mut self) -> Option<Decl> {
+        let start = self.prev.span;
+        let name = self.ident("Expect class name.")?;
+
+        let mut superclass = None;
+        if self.matches(TokenType::Less) {
+            superclass = Some(self.ident("Expect superclass name.")?);
+        }
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            methods.push(self.function("Expect method name.")?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+
+        Some(Decl::Class(ClassDecl {
+            name,
+            superclass,
+            methods,
+            span: start.to(self.prev.span),
+        }))
+    }
+
+    fn function(&mut self, msg: &str) -> Option<Function> {
+        // span includes `fun` keyword unless it's a method
+        let is_fun = self.prev.tt == TokenType::Fun;
+        let fun_span = self.prev.span;
+
+        let name = self.ident(msg)?;
+        let start = if is_fun { fun_span } else { name.span };
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGS {
+                    self.error_at_curr("Can't have more than 255 parameters.");
+                }
+                params.push(self.ident("Expect parameter name.")?);
+
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.block()?;
+
+        Some(Function {
+            name,
+            params,
+            body,
+            span: start.to(self.prev.span),
+        })
+    }
+
+    fn var_decl(&mut self) -> Option<Decl> {
+        let start = self.prev.span;
+        let name = self.ident("Expect variable name.")?;
+
+        let mut init = None;
+        if self.matches(TokenType::Equal) {
+            init = Some(self.expression()?);
+        }
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Some(Decl::Var(VarDecl {
+            name,
+            init,
+            span: start.to(self.prev.span),
+        }))
+    }
+
+    /* Statements */
+
+    fn statement(&mut self) -> Option<Stmt> {
+        let start = self.curr.span;
+
+        let kind = if self.matches(TokenType::Print) {
+            let expr = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+            StmtKind::Print(expr)
+        } else if self.matches(TokenType::LeftBrace) {
+            StmtKind::Block(self.block()?)
+        } else if self.matches(TokenType::If) {
+            self.if_stmt()?
+        } else if self.matches(TokenType::While) {
+            self.while_stmt()?
+        } else if self.matches(TokenType::For) {
+            self.for_stmt()?
+        } else if self.matches(TokenType::Return) {
+            let mut value = None;
+            if !self.check(TokenType::Semicolon) {
+                value = Some(self.expression()?);
+            }
+            self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+            StmtKind::Return(value)
+        } else {
+            let expr = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+            StmtKind::Expr(expr)
+        };
+
+        Some(Stmt {
+            kind,
+            span: start.to(self.prev.span),
+        })
+    }
+
+    // a top level expression statement, or as `Err` the
+    // expression ending the program, which has no `;`
+    fn expr_stmt(&mut self) -> Option<Result<Stmt, Expr>> {
+        let start = self.curr.span;
+        let expr = self.expression()?;
+        if self.check(TokenType::Eof) {
+            return Some(Err(expr));
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+
+        Some(Ok(Stmt {
+            kind: StmtKind::Expr(expr),
+            span: start.to(self.prev.span),
+        }))
+    }
+
+    // left brace is consumed
+    fn block(&mut self) -> Option<Vec<Decl>> {
+        let mut decls = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            if let Some(decl) = self.declaration() {
+                decls.push(decl);
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Some(decls)
+    }
+
+    fn if_stmt(&mut self) -> Option<StmtKind> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let cond = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+
+        let then = Box::new(self.statement()?);
+        let mut els = None;
+        if self.matches(TokenType::Else) {
+            els = Some(Box::new(self.statement()?));
+        }
+        Some(StmtKind::If { cond, then, els })
+    }
+
+    fn while_stmt(&mut self) -> Option<StmtKind> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let cond = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+
+        let body = Box::new(self.statement()?);
+        Some(StmtKind::While { cond, body })
+    }
+
+    fn for_stmt(&mut self) -> Option<StmtKind> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        let init = if self.matches(TokenType::Semicolon) {
+            None
+        } else if self.matches(TokenType::Var) {
+            Some(Box::new(self.var_decl()?))
+        } else {
+            let start = self.curr.span;
+            let expr = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+
+            let span = start.to(self.prev.span);
+            let stmt = Stmt {
+                kind: StmtKind::Expr(expr),
+                span,
+            };
+            Some(Box::new(Decl::Stmt(stmt)))
+        };
+
+        let mut cond = None;
+        if !self.check(TokenType::Semicolon) {
+            cond = Some(self.expression()?);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let mut incr = None;
+        if !self.check(TokenType::RightParen) {
+            incr = Some(self.expression()?);
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = Box::new(self.statement()?);
+        Some(StmtKind::For {
+            init,
+            cond,
+            incr,
+            body,
+        })
+    }
+
+    /* Expressions, from lowest precedence */
+
+    fn expression(&mut self) -> Option<Expr> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Option<Expr> {
+        let target = self.or()?;
+
+        if !self.matches(TokenType::Equal) {
+            return Some(target);
+        }
+        let value = Box::new(self.assignment()?);
+        let span = target.span.to(value.span);
+
+        match target.kind {
+            ExprKind::Variable(name) => Some(Expr {
+                kind: ExprKind::Assign { name, value },
+                span,
+            }),
+            ExprKind::Get { object, name } => Some(Expr {
+                kind: ExprKind::Set {
+                    object,
+                    name,
+                    value,
+                },
+                span,
+            }),
+            _ => {
+                self.error_at_prev("Invalid assignment target.");
+                None
+            }
+        }
+    }
+
+    fn or(&mut self) -> Option<Expr> {
+        let mut expr = self.and()?;
+
+        while self.matches(TokenType::Or) {
+            let right = self.and()?;
+            expr = logical(LogicalOp::Or, expr, right);
+        }
+        Some(expr)
+    }
+
+    fn and(&mut self) -> Option<Expr> {
+        let mut expr = self.equality()?;
+
+        while self.matches(TokenType::And) {
+            let right = self.equality()?;
+            expr = logical(LogicalOp::And, expr, right);
+        }
+        Some(expr)
+    }
+
+    fn equality(&mut self) -> Option<Expr> {
+        let mut expr = self.comparison()?;
+
+        loop {
+            let op = match self.curr.tt {
+                TokenType::EqualEqual => BinaryOp::Equal,
+                TokenType::BangEqual => BinaryOp::NotEqual,
+                _ => return Some(expr),
+            };
+            self.advance();
+            let right = self.comparison()?;
+            expr = binary(op, expr, right);
+        }
+    }
+
+    fn comparison(&mut self) -> Option<Expr> {
+        let mut expr = self.term()?;
+
+        loop {
+            let op = match self.curr.tt {
+                TokenType::Greater => BinaryOp::Greater,
+                TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+                TokenType::Less => BinaryOp::Less,
+                TokenType::LessEqual => BinaryOp::LessEqual,
+                _ => return Some(expr),
+            };
+            self.advance();
+            let right = self.term()?;
+            expr = binary(op, expr, right);
+        }
+    }
+
+    fn term(&mut self) -> Option<Expr> {
+        let mut expr = self.factor()?;
+
+        loop {
+            let op = match self.curr.tt {
+                TokenType::Plus => BinaryOp::Add,
+                TokenType::Minus => BinaryOp::Subtract,
+                _ => return Some(expr),
+            };
+            self.advance();
+            let right = self.factor()?;
+            expr = binary(op, expr, right);
+        }
+    }
+
+    fn factor(&mut self) -> Option<Expr> {
+        let mut expr = self.unary()?;
+
+        loop {
+            let op = match self.curr.tt {
+                TokenType::Star => BinaryOp::Multiply,
+                TokenType::Slash => BinaryOp::Divide,
+                _ => return Some(expr),
+            };
+            self.advance();
+            let right = self.unary()?;
+            expr = binary(op, expr, right);
+        }
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        let op = match self.curr.tt {
+            TokenType::Minus => UnaryOp::Negate,
+            TokenType::Bang => UnaryOp::Not,
+            _ => return self.call(),
+        };
+        self.advance();
+        let start = self.prev.span;
+        let expr = Box::new(self.unary()?);
+
+        Some(Expr {
+            span: start.to(expr.span),
+            kind: ExprKind::Unary { op, expr },
+        })
+    }
+
+    fn call(&mut self) -> Option<Expr> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.matches(TokenType::LeftParen) {
+                let mut args = Vec::new();
+                if !self.check(TokenType::RightParen) {
+                    loop {
+                        if args.len() >= MAX_ARGS {
+                            self.error_at_curr("Can't have more than 255 arguments.");
+                        }
+                        args.push(self.expression()?);
+
+                        if !self.matches(TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+
+                expr = Expr {
+                    span: expr.span.to(self.prev.span),
+                    kind: ExprKind::Call {
+                        callee: Box::new(expr),
+                        args,
+                    },
+                };
+            } else if self.matches(TokenType::Dot) {
+                let name = self.ident("Expect property name after '.'.")?;
+
+                expr = Expr {
+                    span: expr.span.to(name.span),
+                    kind: ExprKind::Get {
+                        object: Box::new(expr),
+                        name,
+                    },
+                };
+            } else {
+                return Some(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Option<Expr> {
+        let kind = match self.curr.tt {
+            TokenType::Nil => ExprKind::Nil,
+            TokenType::True => ExprKind::Bool(true),
+            TokenType::False => ExprKind::Bool(false),
+            TokenType::This => ExprKind::This,
+            TokenType::Number => ExprKind::Number(self.curr.value.parse().unwrap()),
+            TokenType::Str => ExprKind::Str(self.curr.value.to_string()),
+            TokenType::Identifier => ExprKind::Variable(Ident {
+                name: self.curr.value.to_string(),
+                span: self.curr.span,
+            }),
+            TokenType::Super => {
+                self.advance();
+                let start = self.prev.span;
+                self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+                let method = self.ident("Expect superclass method name.")?;
+
+                return Some(Expr {
+                    span: start.to(method.span),
+                    kind: ExprKind::Super(method),
+                });
+            }
+            TokenType::LeftParen => {
+                self.advance();
+                let start = self.prev.span;
+                let expr = self.expression()?;
+                self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+
+                return Some(Expr {
+                    span: start.to(self.prev.span),
+                    kind: ExprKind::Grouping(Box::new(expr)),
+                });
+            }
+            _ => {
+                // consumed, so recovery makes progress after a `;`
+                self.error_at_curr("Expect expression.");
+                self.advance();
+                return None;
+            }
+        };
+        self.advance();
+
+        Some(Expr {
+            kind,
+            span: self.prev.span,
+        })
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr {
+        span: left.span.to(right.span),
+        kind: ExprKind::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    }
+}
+
+fn logical(op: LogicalOp, left: Expr, right: Expr) -> Expr {
+    Expr {
+        span: left.span.to(right.span),
+        kind: ExprKind::Logical {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    }
+}
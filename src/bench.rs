@@ -0,0 +1,154 @@
+// Benchmark harness behind `rlox bench`. Each script is compiled and
+// run a few times, keeping the fastest run:
+//
+//   compile  scanning, parsing & optimizing, in ms
+//   run      executing the chunk, in ms
+//   ops      instructions executed
+//   gc       collector stats, null until there is a heap
+//
+// Output is a table, or JSON with `--json` to diff between commits.
+// Program output is dropped so it doesn't land in the report, and
+// the VM is built before the timed runs.
+//
+// Constants aren't folded, so scripts measure the VM instead of
+// the compiler's arithmetic. Valid Lox the VM can't run yet is
+// reported as unsupported.
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::ast::parse;
+use crate::chunk::*;
+use crate::compiler::compile_chunk;
+use crate::optimize::optimize;
+use crate::verify::verify;
+use crate::vm::*;
+
+pub const RUNS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    OK,
+    CompileError,
+    // parses, but uses what the VM doesn't have yet
+    Unsupported,
+    RuntimeError,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub name: String,
+    pub status: Status,
+    pub compile: Duration,
+    pub run: Duration,
+    pub ops: u64,
+}
+
+/// Compile & run `source` at optimization `level`, fastest of `RUNS`.
+pub fn bench(name: &str, source: &str, level: u8) -> Report {
+    let mut report = Report {
+        name: String::from(name),
+        status: Status::OK,
+        compile: Duration::MAX,
+        run: Duration::ZERO,
+        ops: 0,
+    };
+
+    let mut compiled = Chunk::new();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let chk = match compile_chunk(source, false) {
+            Ok(chk) => chk,
+            Err(_) => {
+                report.status = match parse(source) {
+                    Ok(_) => Status::Unsupported,
+                    Err(_) => Status::CompileError,
+                };
+                report.compile = start.elapsed();
+                return report;
+            }
+        };
+        compiled = optimize(&chk, level);
+        report.compile = report.compile.min(start.elapsed());
+    }
+
+    let (run, status) = time(&compiled);
+    report.run = run;
+    if status == InterpretResult::RuntimeError {
+        report.status = Status::RuntimeError;
+    }
+    let mut counter = Capture::default();
+    VirtualMachine::new().debug(&compiled, &mut counter);
+    report.ops = counter.ops;
+    report
+}
+
+/// Fastest of `RUNS` runs of `chunk` & how the last one ended.
+/// Only the runs are timed, on one VM built before them. A chunk
+/// that fails verification isn't run and is a `CompileError`.
+pub fn time(chunk: &Chunk) -> (Duration, InterpretResult) {
+    if verify(chunk).is_err() {
+        return (Duration::ZERO, InterpretResult::CompileError);
+    }
+    let mut vm = VirtualMachine::new();
+    let mut counter = Capture::default();
+    let mut fastest = Duration::MAX;
+    let mut status = InterpretResult::OK;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        status = vm.rerun(chunk, &mut counter);
+        fastest = fastest.min(start.elapsed());
+    }
+    (fastest, status)
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::OK => "ok",
+            Status::CompileError => "compile error",
+            Status::Unsupported => "unsupported",
+            Status::RuntimeError => "runtime error",
+        }
+    }
+}
+
+impl Report {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "status": self.status.name(),
+            "compile_ms": millis(self.compile),
+            "run_ms": millis(self.run),
+            "ops": self.ops,
+            "gc": Value::Null,
+        })
+    }
+}
+
+/// Header & one row per report, failed scripts show their status.
+pub fn table(reports: &[Report]) -> String {
+    let mut out = format!(
+        "{:<20} {:>12} {:>12} {:>12}\n",
+        "benchmark", "compile ms", "run ms", "ops"
+    );
+    for report in reports.iter() {
+        let row = match report.status {
+            Status::OK => format!(
+                "{:<20} {:>12.3} {:>12.3} {:>12}\n",
+                report.name,
+                millis(report.compile),
+                millis(report.run),
+                report.ops
+            ),
+            status => format!("{:<20} {:>12}\n", report.name, status.name()),
+        };
+        out.push_str(&row);
+    }
+    out
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,209 @@
+// Binary chunk format, compile once & run many.
+//
+//   magic     b"LOXC"
+//   version   u16
+//   code      u32 length, then the code bytes as is
+//   constants u32 count, then a tag & payload each
+//   names     u32 count, then u32 length & UTF-8 bytes each
+//   lines     u32 count of runs, then (start u32, line u32, col u32) each
+//   checksum  u32, FNV-1a of everything before it
+//
+// Integers are little endian. Bump `VERSION` whenever the
+// layout or the meaning of an op changes.
+
+use std::fmt;
+
+use crate::chunk::*;
+use crate::verify::{verify, VerifyError};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 6;
+
+// constant tags, strings & functions will follow
+const TAG_NUMBER: u8 = 0;
+const TAG_NIL: u8 = 1;
+const TAG_BOOL: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    NotBytecode,
+    Version(u16),
+    Checksum,
+    Truncated,
+    BadConstant(u8),
+    BadName,
+    Invalid(VerifyError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "Not a bytecode file."),
+            LoadError::Version(v) => write!(
+                f,
+                "Bytecode version {} is not supported, expect {}.",
+                v, VERSION
+            ),
+            LoadError::Checksum => write!(f, "Bytecode checksum mismatch."),
+            LoadError::Truncated => write!(f, "Bytecode is truncated."),
+            LoadError::BadConstant(tag) => write!(f, "Unknown constant type {}.", tag),
+            LoadError::BadName => write!(f, "Name is not valid UTF-8."),
+            LoadError::Invalid(err) => write!(f, "Invalid bytecode: {}", err),
+        }
+    }
+}
+
+// true if `bytes` look like a compiled chunk
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn serialize(bytes: &Chunk) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+
+    write_u32(&mut out, bytes.code.len());
+    out.extend_from_slice(&bytes.code);
+
+    write_u32(&mut out, bytes.constants.len());
+    for val in bytes.constants.iter() {
+        if let Some(n) = val.as_number() {
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&n.to_bits().to_le_bytes());
+        } else if let Some(b) = val.as_bool() {
+            out.extend_from_slice(&[TAG_BOOL, b as u8]);
+        } else if val.is_nil() {
+            out.push(TAG_NIL);
+        } else {
+            unreachable!("natives are never constants");
+        }
+    }
+
+    write_u32(&mut out, bytes.names.len());
+    for name in bytes.names.iter() {
+        write_u32(&mut out, name.len());
+        out.extend_from_slice(name.as_bytes());
+    }
+
+    // already run length encoded
+    write_u32(&mut out, bytes.lines.len());
+    for run in bytes.lines.iter() {
+        write_u32(&mut out, run.start);
+        write_u32(&mut out, run.line);
+        write_u32(&mut out, run.col);
+    }
+
+    let checksum = fnv1a(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+// load & verify a chunk
+pub fn deserialize(data: &[u8]) -> Result<Chunk, LoadError> {
+    if !is_bytecode(data) {
+        return Err(LoadError::NotBytecode);
+    }
+    let mut reader = Reader { data, pos: 4 };
+
+    // check version first, older files may checksum differently
+    let version = u16::from_le_bytes([reader.byte()?, reader.byte()?]);
+    if version != VERSION {
+        return Err(LoadError::Version(version));
+    }
+    if data.len() < 10 {
+        return Err(LoadError::Truncated);
+    }
+    let (body, checksum) = data.split_at(data.len() - 4);
+    if fnv1a(body).to_le_bytes() != checksum {
+        return Err(LoadError::Checksum);
+    }
+    reader.data = body;
+
+    let mut chunk = Chunk::new();
+    for _ in 0..reader.u32()? {
+        chunk.code.push(reader.byte()?);
+    }
+    chunk.count = chunk.code.len();
+    chunk.capacity = chunk.code.capacity();
+
+    for _ in 0..reader.u32()? {
+        match reader.byte()? {
+            TAG_NUMBER => {
+                let mut bits = [0; 8];
+                for b in bits.iter_mut() {
+                    *b = reader.byte()?;
+                }
+                let n = f64::from_bits(u64::from_le_bytes(bits));
+                chunk.constants.push(Value::from(n));
+            }
+            TAG_NIL => chunk.constants.push(Value::nil()),
+            TAG_BOOL => {
+                let b = reader.byte()? != 0;
+                chunk.constants.push(Value::from(b));
+            }
+            tag => return Err(LoadError::BadConstant(tag)),
+        }
+    }
+
+    for _ in 0..reader.u32()? {
+        let mut name = Vec::new();
+        for _ in 0..reader.u32()? {
+            name.push(reader.byte()?);
+        }
+        let name = String::from_utf8(name).map_err(|_| LoadError::BadName)?;
+        chunk.push_name(name);
+    }
+
+    for _ in 0..reader.u32()? {
+        chunk.lines.push(LineRun {
+            start: reader.u32()?,
+            line: reader.u32()?,
+            col: reader.u32()?,
+        });
+    }
+
+    if reader.pos != body.len() {
+        return Err(LoadError::Truncated);
+    }
+    // the checksum can't tell a broken compiler or a crafted file
+    verify(&chunk).map_err(LoadError::Invalid)?;
+    Ok(chunk)
+}
+
+/* Encoding */
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+// 32 bit FNV-1a, catches corruption not tampering
+fn fnv1a(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for b in data.iter() {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        let b = *self.data.get(self.pos).ok_or(LoadError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        let mut bytes = [0; 4];
+        for b in bytes.iter_mut() {
+            *b = self.byte()?;
+        }
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+}
@@ -1,12 +1,17 @@
-pub type Value = f64;
+use std::collections::HashMap;
 
+pub use crate::value::Value;
+
+// indices past u8 use the 24 bit long form
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
+// opcodes, operands follow inline in the code
 #[allow(dead_code)]
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Op {
-    // default type is Chunk::Op
+    // 1 byte constant index
     Constant,
-    ConstantIndex(u8),
     Negate,
     Add,
     Subtract,
@@ -14,6 +19,144 @@ pub enum Op {
     Divide,
     Return,
     Nil,
+    // 3 byte constant index, little endian
+    ConstantLong,
+    // superinstructions, `Constant x; Add` etc.
+    // fused by the optimizer, 1 byte constant index
+    AddConstant,
+    SubtractConstant,
+    MultiplyConstant,
+    DivideConstant,
+    // 1 or 3 byte name index, see `GetGlobal` in `Instruction`
+    GetGlobal,
+    GetGlobalLong,
+    // 1 byte argument count
+    Call,
+    Print,
+    Pop,
+    True,
+    False,
+    Not,
+    Equal,
+    // `a >= b` isn't `!(a < b)` when one is NaN,
+    // so both have their op
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl Op {
+    pub fn from_byte(byte: u8) -> Option<Op> {
+        let op = match byte {
+            0 => Op::Constant,
+            1 => Op::Negate,
+            2 => Op::Add,
+            3 => Op::Subtract,
+            4 => Op::Multiply,
+            5 => Op::Divide,
+            6 => Op::Return,
+            7 => Op::Nil,
+            8 => Op::ConstantLong,
+            9 => Op::AddConstant,
+            10 => Op::SubtractConstant,
+            11 => Op::MultiplyConstant,
+            12 => Op::DivideConstant,
+            13 => Op::GetGlobal,
+            14 => Op::GetGlobalLong,
+            15 => Op::Call,
+            16 => Op::Print,
+            17 => Op::Pop,
+            18 => Op::True,
+            19 => Op::False,
+            20 => Op::Not,
+            21 => Op::Equal,
+            22 => Op::Greater,
+            23 => Op::GreaterEqual,
+            24 => Op::Less,
+            25 => Op::LessEqual,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+impl From<Op> for u8 {
+    fn from(op: Op) -> u8 {
+        op as u8
+    }
+}
+
+// an op with its operands decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Constant(u8),
+    ConstantLong(usize),
+    Negate,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Return,
+    Nil,
+    AddConstant(u8),
+    SubtractConstant(u8),
+    MultiplyConstant(u8),
+    DivideConstant(u8),
+    // global named by its index in the name table
+    GetGlobal(u8),
+    GetGlobalLong(usize),
+    // callee & arguments on the stack
+    Call(u8),
+    Print,
+    Pop,
+    True,
+    False,
+    Not,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl Instruction {
+    // bytes taken in the code
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::ConstantLong(_) | Instruction::GetGlobalLong(_) => 4,
+            Instruction::Constant(_)
+            | Instruction::AddConstant(_)
+            | Instruction::SubtractConstant(_)
+            | Instruction::MultiplyConstant(_)
+            | Instruction::DivideConstant(_)
+            | Instruction::GetGlobal(_)
+            | Instruction::Call(_) => 2,
+            _ => 1,
+        }
+    }
+
+    // constant pool index it reads
+    pub fn constant(&self) -> Option<usize> {
+        match self {
+            Instruction::ConstantLong(idx) => Some(*idx),
+            Instruction::Constant(idx)
+            | Instruction::AddConstant(idx)
+            | Instruction::SubtractConstant(idx)
+            | Instruction::MultiplyConstant(idx)
+            | Instruction::DivideConstant(idx) => Some(*idx as usize),
+            _ => None,
+        }
+    }
+
+    // name table index it reads
+    pub fn name(&self) -> Option<usize> {
+        match self {
+            Instruction::GetGlobal(idx) => Some(*idx as usize),
+            Instruction::GetGlobalLong(idx) => Some(*idx),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -21,80 +164,123 @@ pub struct Chunk {
     // Rust array can only be created using
     // compile time known length, thus
     // you can't do sth like: let code = [0; len];
-    pub code: Vec<Op>,
+    pub code: Vec<u8>,
     pub count: usize,
     pub capacity: usize,
     // Line information for error report
     // compressed with run-length encoding
-    lines: Vec<usize>,
+    pub(crate) lines: Vec<LineRun>,
     // store constants
-    constants: Vec<Value>,
+    pub(crate) constants: Vec<Value>,
+    // constant bits -> index, to reuse equal constants
+    constant_index: HashMap<u64, usize>,
+    // identifiers, until strings are values
+    pub(crate) names: Vec<String>,
+    // name -> index, like `constant_index`
+    name_index: HashMap<String, usize>,
 }
 
 /* Constant Get & Set */
 pub trait ConstantTrait {
-    fn add_constant(&mut self, value: Value) -> Op;
-    fn get_constant(&self, offset: usize) -> (u8, Value);
+    fn add_constant<V: Into<Value>>(&mut self, value: V) -> Option<usize>;
+    fn write_constant<V: Into<Value>>(&mut self, value: V, line: usize) -> Option<usize>;
+    fn get_constant(&self, offset: usize) -> (usize, Value);
 }
 
 impl ConstantTrait for Chunk {
-    // return constant index
-    fn add_constant(&mut self, value: Value) -> Op {
-        if self.constants.len() >= 256 {
-            panic!("A chunk cannot have more than 256 constants");
+    // return constant index, None if the pool is full
+    //
+    // equal constants share an index, compared bitwise so
+    // 0.0 & -0.0 stay apart and a NaN is equal to itself
+    fn add_constant<V: Into<Value>>(&mut self, value: V) -> Option<usize> {
+        let value = value.into();
+        if let Some(idx) = self.constant_index.get(&value.to_bits()) {
+            return Some(*idx);
+        }
+        if self.constants.len() >= MAX_CONSTANTS {
+            return None;
         }
         self.constants.push(value);
-        Op::ConstantIndex(self.constants.len() as u8 - 1)
+        self.constant_index
+            .insert(value.to_bits(), self.constants.len() - 1);
+        Some(self.constants.len() - 1)
+    }
+
+    // add & load a constant, short or long form by index
+    fn write_constant<V: Into<Value>>(&mut self, value: V, line: usize) -> Option<usize> {
+        let idx = self.add_constant(value)?;
+        self.write_indexed(Op::Constant, Op::ConstantLong, idx, line, 0);
+        Some(idx)
     }
 
     // return constant index & value
     //   offset: op offset in chunk
-    fn get_constant(&self, offset: usize) -> (u8, Value) {
-        if let Op::ConstantIndex(idx) = self.code[offset + 1] {
-            return (idx, self.constants[idx as usize]);
+    fn get_constant(&self, offset: usize) -> (usize, Value) {
+        match self.decode(offset) {
+            Some(Instruction::Constant(idx)) => (idx as usize, self.constants[idx as usize]),
+            Some(Instruction::ConstantLong(idx)) => (idx, self.constants[idx]),
+            _ => panic!("Cannot access constant"),
         }
-        panic!("Cannot access constant");
     }
 }
 
 /* Line Get & Set */
+
+// code from `start` up to the next run comes from one
+// source position, col is 0 when unknown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRun {
+    pub start: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
 pub trait LineTrait {
-    fn add_line(&mut self, ln: usize);
+    fn add_line(&mut self, line: usize, col: usize);
     fn get_line(&self, offset: usize) -> usize;
+    fn get_position(&self, offset: usize) -> (usize, usize);
+    fn line_offsets(&self, line: usize) -> Vec<usize>;
 }
 
 impl LineTrait for Chunk {
-    // run length encoding
-    // 1,1,1,3,3 -> 1,3,3,2
-    fn add_line(&mut self, ln: usize) {
-        let len = self.lines.len();
-        if len == 0 {
-            self.lines.push(ln);
-            self.lines.push(1);
-        } else {
-            let last_ln = self.lines[len - 2];
-            if ln == last_ln {
-                self.lines[len - 1] += 1;
-            } else {
-                self.lines.push(ln);
-                self.lines.push(1);
-            }
+    // run length encoding, keyed by start offset
+    // lines 1,1,1,3,3 -> (0, 1), (3, 3)
+    fn add_line(&mut self, line: usize, col: usize) {
+        match self.lines.last() {
+            Some(run) if run.line == line && run.col == col => {}
+            _ => self.lines.push(LineRun {
+                start: self.count,
+                line,
+                col,
+            }),
         }
     }
 
-    // run length decoding
+    // 0 if out of the code
     fn get_line(&self, offset: usize) -> usize {
-        let len = self.lines.len();
-        let mut i = 0;
-        let mut total_op = 0;
-        while i < len {
-            total_op += self.lines[i + 1];
-            if total_op > offset {
-                return self.lines[i];
-            }
-            i += 2;
+        self.get_position(offset).0
+    }
+
+    // binary search the run holding `offset`
+    fn get_position(&self, offset: usize) -> (usize, usize) {
+        if offset >= self.count {
+            return (0, 0);
+        }
+        let idx = self.lines.partition_point(|run| run.start <= offset);
+        match idx.checked_sub(1).map(|i| self.lines[i]) {
+            Some(run) => (run.line, run.col),
+            None => (0, 0),
         }
-        0
+    }
+
+    // first offset of each run on `line`, where a
+    // breakpoint on it would stop
+    fn line_offsets(&self, line: usize) -> Vec<usize> {
+        self.lines
+            .iter()
+            .filter(|run| run.line == line)
+            .map(|run| run.start)
+            .collect()
     }
 }
 
@@ -104,6 +290,9 @@ impl Chunk {
         Chunk {
             code: Vec::new(),
             constants: Vec::new(),
+            constant_index: HashMap::new(),
+            names: Vec::new(),
+            name_index: HashMap::new(),
             lines: Vec::new(),
             count: 0,
             capacity: 0,
@@ -111,21 +300,167 @@ impl Chunk {
     }
 
     // let mut chunk = Chunk::new();
-    // chunk.write(Op::Return);
-    pub fn write(&mut self, byte: Op, line: usize) {
+    // chunk.write(Op::Return, 1);
+    pub fn write<B: Into<u8>>(&mut self, byte: B, line: usize) {
+        self.write_at(byte, line, 0);
+    }
+
+    // write with source column
+    pub fn write_at<B: Into<u8>>(&mut self, byte: B, line: usize, col: usize) {
         if self.capacity < self.count + 1 {
             self.capacity = grow_capacity!(self.capacity);
         }
 
-        self.code.push(byte);
-        self.add_line(line);
+        self.add_line(line, col);
+        self.code.push(byte.into());
         self.count += 1;
     }
 
+    // remove the last constant, it must not be loaded
+    pub fn pop_constant(&mut self) {
+        if let Some(value) = self.constants.pop() {
+            self.constant_index.remove(&value.to_bits());
+        }
+    }
+
+    // drop code from `offset` on, constants are kept
+    pub fn truncate(&mut self, offset: usize) {
+        self.code.truncate(offset);
+        self.count = self.code.len();
+        while self.lines.last().is_some_and(|run| run.start >= self.count) {
+            self.lines.pop();
+        }
+    }
+
+    // index of `name` in the name table, None if it's full
+    pub fn add_name(&mut self, name: &str) -> Option<usize> {
+        if let Some(idx) = self.name_index.get(name) {
+            return Some(*idx);
+        }
+        if self.names.len() >= MAX_CONSTANTS {
+            return None;
+        }
+        self.push_name(String::from(name));
+        Some(self.names.len() - 1)
+    }
+
+    // append to the name table as is, for a loaded chunk
+    // whose indices must not move
+    pub(crate) fn push_name(&mut self, name: String) {
+        self.name_index
+            .entry(name.clone())
+            .or_insert(self.names.len());
+        self.names.push(name);
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn line_runs(&self) -> &[LineRun] {
+        &self.lines
+    }
+
+    // op with an index operand, `long` takes 3 bytes
+    // when it doesn't fit in `short`'s 1 byte
+    pub fn write_indexed(&mut self, short: Op, long: Op, idx: usize, line: usize, col: usize) {
+        if idx <= u8::MAX as usize {
+            self.write_at(short, line, col);
+            self.write_at(idx as u8, line, col);
+        } else {
+            self.write_at(long, line, col);
+            for b in idx.to_le_bytes()[..3].iter() {
+                self.write_at(*b, line, col);
+            }
+        }
+    }
+
+    // None on unknown op or missing operands,
+    // only unverified chunks can have them
+    pub fn decode(&self, offset: usize) -> Option<Instruction> {
+        let ins = match Op::from_byte(*self.code.get(offset)?)? {
+            Op::Constant => Instruction::Constant(*self.code.get(offset + 1)?),
+            Op::ConstantLong => Instruction::ConstantLong(self.read_u24(offset + 1)?),
+            Op::Negate => Instruction::Negate,
+            Op::Add => Instruction::Add,
+            Op::Subtract => Instruction::Subtract,
+            Op::Multiply => Instruction::Multiply,
+            Op::Divide => Instruction::Divide,
+            Op::Return => Instruction::Return,
+            Op::Nil => Instruction::Nil,
+            Op::AddConstant => Instruction::AddConstant(*self.code.get(offset + 1)?),
+            Op::SubtractConstant => Instruction::SubtractConstant(*self.code.get(offset + 1)?),
+            Op::MultiplyConstant => Instruction::MultiplyConstant(*self.code.get(offset + 1)?),
+            Op::DivideConstant => Instruction::DivideConstant(*self.code.get(offset + 1)?),
+            Op::GetGlobal => Instruction::GetGlobal(*self.code.get(offset + 1)?),
+            Op::GetGlobalLong => Instruction::GetGlobalLong(self.read_u24(offset + 1)?),
+            Op::Call => Instruction::Call(*self.code.get(offset + 1)?),
+            Op::Print => Instruction::Print,
+            Op::Pop => Instruction::Pop,
+            Op::True => Instruction::True,
+            Op::False => Instruction::False,
+            Op::Not => Instruction::Not,
+            Op::Equal => Instruction::Equal,
+            Op::Greater => Instruction::Greater,
+            Op::GreaterEqual => Instruction::GreaterEqual,
+            Op::Less => Instruction::Less,
+            Op::LessEqual => Instruction::LessEqual,
+        };
+        Some(ins)
+    }
+
+    // encode, the reverse of `decode`
+    pub fn write_instruction(&mut self, ins: Instruction, line: usize, col: usize) {
+        let (op, operand) = match ins {
+            Instruction::ConstantLong(idx) => {
+                return self.write_indexed(Op::Constant, Op::ConstantLong, idx, line, col)
+            }
+            Instruction::GetGlobalLong(idx) => {
+                return self.write_indexed(Op::GetGlobal, Op::GetGlobalLong, idx, line, col)
+            }
+            Instruction::Constant(idx) => (Op::Constant, Some(idx)),
+            Instruction::Negate => (Op::Negate, None),
+            Instruction::Add => (Op::Add, None),
+            Instruction::Subtract => (Op::Subtract, None),
+            Instruction::Multiply => (Op::Multiply, None),
+            Instruction::Divide => (Op::Divide, None),
+            Instruction::Return => (Op::Return, None),
+            Instruction::Nil => (Op::Nil, None),
+            Instruction::AddConstant(idx) => (Op::AddConstant, Some(idx)),
+            Instruction::SubtractConstant(idx) => (Op::SubtractConstant, Some(idx)),
+            Instruction::MultiplyConstant(idx) => (Op::MultiplyConstant, Some(idx)),
+            Instruction::DivideConstant(idx) => (Op::DivideConstant, Some(idx)),
+            Instruction::GetGlobal(idx) => (Op::GetGlobal, Some(idx)),
+            Instruction::Call(argc) => (Op::Call, Some(argc)),
+            Instruction::Print => (Op::Print, None),
+            Instruction::Pop => (Op::Pop, None),
+            Instruction::True => (Op::True, None),
+            Instruction::False => (Op::False, None),
+            Instruction::Not => (Op::Not, None),
+            Instruction::Equal => (Op::Equal, None),
+            Instruction::Greater => (Op::Greater, None),
+            Instruction::GreaterEqual => (Op::GreaterEqual, None),
+            Instruction::Less => (Op::Less, None),
+            Instruction::LessEqual => (Op::LessEqual, None),
+        };
+        self.write_at(op, line, col);
+        if let Some(operand) = operand {
+            self.write_at(operand, line, col);
+        }
+    }
+
+    pub(crate) fn read_u24(&self, offset: usize) -> Option<usize> {
+        let bytes = self.code.get(offset..offset + 3)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) as usize)
+    }
+
     #[allow(dead_code)]
     pub fn clear(&mut self) {
         self.code.clear();
         self.constants.clear();
+        self.constant_index.clear();
+        self.names.clear();
+        self.name_index.clear();
         self.lines.clear();
         self.count = 0;
         self.capacity = 0;
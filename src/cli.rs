@@ -0,0 +1,263 @@
+// The `rlox` command line, built on the library's public API.
+
+use rlox::bytecode;
+use rlox::optimize::{optimize, MAX_LEVEL};
+use rlox::vm::InterpretResult;
+use rlox::{Config, LoxError, Value, Vm};
+
+pub fn repl() {
+    use rustyline::error::ReadlineError;
+    use rustyline::Editor;
+
+    // one VM for the session, ready for lines that define globals
+    let mut vm = Vm::default();
+    let mut rl = Editor::<()>::new();
+    loop {
+        let readline = rl.readline(">> ");
+        match readline {
+            Ok(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rl.add_history_entry(line.as_str());
+                // the value of the line is printed
+                report(vm.eval(&line));
+            }
+            Err(ReadlineError::Interrupted) => break,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        }
+    }
+}
+
+pub fn run_file(filename: &str, level: u8) {
+    use std::fs::File;
+    use std::io::Read;
+
+    println!("running file: {}", filename);
+
+    let mut buffer = Vec::new();
+    {
+        File::open(filename)
+            .expect("failed to open file")
+            .read_to_end(&mut buffer)
+            .expect("failed to read file");
+    }
+    let mut vm = Vm::new(Config {
+        opt_level: level,
+        ..Config::default()
+    });
+    // precompiled by `rlox compile`
+    let result = if bytecode::is_bytecode(&buffer) {
+        match bytecode::deserialize(&buffer) {
+            Ok(chk) => vm.run(&chk),
+            Err(err) => {
+                eprintln!("{}: {}", filename, err);
+                std::process::exit(65);
+            }
+        }
+    } else {
+        let source = String::from_utf8(buffer).expect("failed to read file");
+        vm.eval(&source)
+    };
+    match report(result) {
+        0 => {}
+        status => std::process::exit(status),
+    }
+}
+
+// print the value unless it's nil, or the error, return
+// the exit status
+fn report(result: Result<Value, LoxError>) -> i32 {
+    match result {
+        Ok(val) => {
+            if !val.is_nil() {
+                println!("{}", val);
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            match err {
+                LoxError::Runtime(_) => 70,
+                _ => 65,
+            }
+        }
+    }
+}
+
+// remove `-O<level>` from args, `-O` alone is the highest level
+pub fn take_opt_level(args: &mut Vec<String>) -> u8 {
+    let mut level = 0;
+    args.retain(|arg| match arg.strip_prefix("-O") {
+        Some("") => {
+            level = MAX_LEVEL;
+            false
+        }
+        Some(n) => match n.parse::<u8>() {
+            Ok(n) => {
+                level = n.min(MAX_LEVEL);
+                false
+            }
+            Err(_) => true,
+        },
+        None => true,
+    });
+    level
+}
+
+// rlox compile [-O<level>] in.lox [-o out.loxc]
+pub fn compile_file(args: &[String], level: u8) {
+    use std::path::Path;
+
+    let (input, output) = match args {
+        [input] => (input, Path::new(input).with_extension("loxc")),
+        [input, flag, output] if flag == "-o" => (input, Path::new(output).to_path_buf()),
+        _ => {
+            println!("Usage: ./rlox compile [-O<level>] in.lox [-o out.loxc]");
+            std::process::exit(64);
+        }
+    };
+    let source = std::fs::read_to_string(input).expect("failed to read file");
+
+    let chk = match rlox::compile(&source) {
+        Ok(chk) => chk,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(65);
+        }
+    };
+    let chk = optimize(&chk, level);
+    std::fs::write(output, bytecode::serialize(&chk)).expect("failed to write file");
+}
+
+// rlox fmt [--check] files...
+pub fn fmt_files(args: &[String]) {
+    use rlox::format::format;
+    use std::fs;
+
+    let check = args.iter().any(|arg| arg == "--check");
+    let mut status = 0;
+
+    for filename in args.iter().filter(|arg| *arg != "--check") {
+        let source = fs::read_to_string(filename).expect("failed to read file");
+
+        let formatted = match format(&source) {
+            Ok(text) => text,
+            Err(errors) => {
+                for err in errors.iter() {
+                    println!("{}: {}", filename, err);
+                }
+                status = 65;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("would reformat: {}", filename);
+            status = status.max(1);
+        } else {
+            fs::write(filename, formatted).expect("failed to write file");
+        }
+    }
+    std::process::exit(status);
+}
+
+// rlox lint files..., warnings don't fail
+pub fn lint_files(args: &[String]) {
+    use rlox::lint::lint;
+
+    let mut status = 0;
+
+    for filename in args.iter() {
+        let source = std::fs::read_to_string(filename).expect("failed to read file");
+
+        let diagnostics = match lint(&source) {
+            Ok(warnings) => warnings,
+            Err(errors) => {
+                status = 65;
+                errors
+            }
+        };
+        for diag in diagnostics.iter() {
+            println!("{}: {}", filename, diag);
+        }
+    }
+    std::process::exit(status);
+}
+
+// rlox bench [--json] [files...], the corpus in benches/lox by default
+pub fn bench_files(args: &[String], level: u8) {
+    use rlox::bench::*;
+    use std::path::{Path, PathBuf};
+
+    let json = args.iter().any(|arg| arg == "--json");
+    let mut files: Vec<PathBuf> = args
+        .iter()
+        .filter(|arg| *arg != "--json")
+        .map(PathBuf::from)
+        .collect();
+    if files.is_empty() {
+        let dir = std::fs::read_dir("benches/lox").expect("failed to read benches/lox");
+        files = dir.map(|entry| entry.unwrap().path()).collect();
+        files.sort();
+    }
+
+    let reports: Vec<Report> = files
+        .iter()
+        .map(|path| {
+            let source = std::fs::read_to_string(path).expect("failed to read file");
+            let name = Path::new(path).file_stem().unwrap().to_string_lossy();
+            bench(&name, &source, level)
+        })
+        .collect();
+
+    if json {
+        let reports: Vec<serde_json::Value> = reports.iter().map(Report::to_json).collect();
+        let out = serde_json::json!({ "opt_level": level, "benchmarks": reports });
+        println!("{}", serde_json::to_string_pretty(&out).unwrap());
+    } else {
+        print!("{}", table(&reports));
+    }
+}
+
+// rlox lsp, talks to editor over stdio
+pub fn serve_lsp() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+
+    if let Err(err) = rlox::lsp::serve(stdin.lock(), stdout.lock()) {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(74);
+    }
+}
+
+// rlox dap, debugs a script for editor over stdio
+pub fn serve_dap() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+
+    if let Err(err) = rlox::dap::serve(stdin.lock(), stdout.lock()) {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(74);
+    }
+}
+
+// rlox debug script.lox, commands from stdin
+pub fn debug_file(filename: &str) {
+    let source = std::fs::read_to_string(filename).expect("failed to read file");
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+
+    let status = rlox::debugger::debug(&source, stdin.lock(), stdout.lock());
+    match status {
+        InterpretResult::OK => {}
+        InterpretResult::CompileError => std::process::exit(65),
+        InterpretResult::RuntimeError => std::process::exit(70),
+    }
+}
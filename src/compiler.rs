@@ -2,18 +2,78 @@
 // A parser produces an AST and then a “code generator” traverses the
 // AST and outputs target code. In rlox, we’re merging these two passes
 // into one.
+//
+// The grammar is the one of `ast/parser.rs`, so tools & the VM agree
+// on what a program is. Declarations, statements & expressions other
+// than these are reported as not supported yet:
+//
+//   program   -> statement* expression? EOF ;
+//   statement -> exprStmt | printStmt ;
+//
+// the expression a program may end in, without `;`, is its value.
 
 use std::option::Option;
 
 use crate::chunk::*;
+use crate::diagnostic::Diagnostic;
 use crate::lexer::*;
+use crate::DEBUG_LEXER;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment, // =
+    Or,         // or
+    And,        // and
+    Equality,   // == !=
+    Comparison, // < > <= >=
+    Term,       // + -
+    Factor,     // * /
+    Unary,      // ! -
+    Call,       // . ()
+    Primary,
+}
+
+impl Precedence {
+    // one level higher, binary ops are left associative
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type ParseFn<'src> = fn(&mut Parser<'src>);
 
 #[derive(Debug)]
 pub struct Parser<'src> {
     curr: Option<Token<'src>>,
     prev: Option<Token<'src>>,
     scanner: &'src mut Scanner<'src>,
+    // chunk being compiled
+    chunk: Chunk,
+    // (offset, constant pool length before it) of emitted
+    // instructions
+    ops: Vec<(usize, usize)>,
+    // the program ended in an expression, left on the stack
+    has_value: bool,
+    // fold constant expressions, debuggers turn it off
+    // to keep code for every line
+    pub fold: bool,
     pub had_error: bool,
+    // reported errors, in source order
+    pub errors: Vec<String>,
+    // the same errors located, for tools
+    pub diagnostics: Vec<Diagnostic>,
     panic: bool,
 }
 
@@ -23,40 +83,154 @@ impl<'src> Parser<'src> {
             curr: None,
             prev: None,
             scanner,
+            chunk: Chunk::new(),
+            ops: Vec::new(),
+            has_value: false,
+            fold: true,
             had_error: false,
+            errors: Vec::new(),
+            diagnostics: Vec::new(),
             panic: false,
         }
     }
 
     pub fn advance(&mut self) {
         self.prev = self.curr.take();
-        self.curr = Some(self.scanner.next());
+        self.curr = Some(self.scanner.scan_token());
 
         if let TokenType::Error = self.curr_token().tt {
             self.lexic_error();
         }
     }
 
-    pub fn curr_token(&self) -> &Token {
+    pub fn curr_token(&self) -> &Token<'src> {
         self.curr.as_ref().unwrap()
     }
 
-    pub fn prev_token(&self) -> &Token {
+    pub fn prev_token(&self) -> &Token<'src> {
         self.prev.as_ref().unwrap()
     }
 
-    fn emit_byte(&mut self, op: Op, bytes: &mut Chunk) {
-    	bytes.write(op, self.prev_token().line);
+    fn check(&self, tt: TokenType) -> bool {
+        self.curr_token().tt == tt
+    }
+
+    fn matches(&mut self, tt: TokenType) -> bool {
+        if !self.check(tt) {
+            return false;
+        }
+        self.advance();
+        true
     }
 
-    fn emit_return(&mut self, bytes: &mut Chunk) {
-    	self.emit_byte(Op::Return, bytes);
+    // single byte op
+    fn emit_byte(&mut self, op: Op) {
+        let token = self.prev_token();
+        let (line, col) = (token.line, token.col);
+        let pool = self.chunk.constants.len();
+        self.ops.push((self.chunk.count, pool));
+        self.chunk.write_at(op, line, col);
     }
 
-    pub fn end_compile(&mut self, bytes: &mut Chunk) {
-    	println!("prev: {:?}, curr: {:?}", self.prev, self.curr);
+    // op & a 1 byte operand
+    fn emit_bytes(&mut self, op: Op, operand: u8) {
+        self.emit_byte(op);
+        let token = self.prev_token();
+        let (line, col) = (token.line, token.col);
+        self.chunk.write_at(operand, line, col);
+    }
 
-    	self.emit_return(bytes);
+    fn emit_return(&mut self) {
+        self.emit_byte(Op::Return);
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let token = self.prev_token();
+        let (line, col) = (token.line, token.col);
+
+        let pool = self.chunk.constants.len();
+        match self.chunk.add_constant(value) {
+            Some(idx) => {
+                self.ops.push((self.chunk.count, pool));
+                self.chunk
+                    .write_indexed(Op::Constant, Op::ConstantLong, idx, line, col)
+            }
+            None => self.prev_error("Too many constants in one chunk."),
+        }
+    }
+
+    // nil, a boolean or a constant, pushed as the op with
+    // the fewest bytes
+    fn emit_value(&mut self, value: Value) {
+        match value.as_bool() {
+            Some(true) => self.emit_byte(Op::True),
+            Some(false) => self.emit_byte(Op::False),
+            None if value.is_nil() => self.emit_byte(Op::Nil),
+            None => self.emit_constant(value),
+        }
+    }
+
+    // value of the op at `offset`, if it loads a literal
+    fn literal_at(&self, offset: usize) -> Option<Value> {
+        let value = match self.chunk.decode(offset)? {
+            Instruction::Constant(idx) => self.chunk.constants[idx as usize],
+            Instruction::ConstantLong(idx) => self.chunk.constants[idx],
+            Instruction::Nil => Value::nil(),
+            Instruction::True => Value::from(true),
+            Instruction::False => Value::from(false),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // values of the last `n` instructions, if all load literals
+    //
+    // an expression ending in a literal load is that literal,
+    // others end in their operator, so these are the operands
+    fn literal_operands(&self, n: usize) -> Option<Vec<Value>> {
+        let start = self.ops.len().checked_sub(n)?;
+        self.ops[start..]
+            .iter()
+            .map(|(offset, _)| self.literal_at(*offset))
+            .collect()
+    }
+
+    // emit `op` on the last `n` operands, or its result if they
+    // are literals, at the position `op` would have had
+    //
+    // `eval` gives None where the VM fails, e.g. adding nil,
+    // the op is left to report it at runtime
+    fn emit_folded(&mut self, op: Op, n: usize, eval: fn(&[Value]) -> Option<Value>) {
+        let values = match self.literal_operands(n) {
+            Some(values) if self.fold => values,
+            _ => return self.emit_byte(op),
+        };
+        let result = match eval(&values) {
+            Some(result) => result,
+            None => return self.emit_byte(op),
+        };
+
+        let (start, pool) = self.ops[self.ops.len() - n];
+        self.ops.truncate(self.ops.len() - n);
+        self.chunk.truncate(start);
+
+        // constants added since the operands began are only loaded
+        // by them, drop them so a long folded expression doesn't
+        // fill the pool with partial results
+        while self.chunk.constants.len() > pool {
+            self.chunk.pop_constant();
+        }
+        self.emit_value(result);
+    }
+
+    // hand out the compiled chunk, it returns the value
+    // of the program or nil
+    pub fn end_compile(&mut self, bytes: &mut Chunk) {
+        if !self.has_value {
+            self.emit_byte(Op::Nil);
+        }
+        self.emit_return();
+        *bytes = std::mem::take(&mut self.chunk);
     }
 }
 
@@ -68,7 +242,7 @@ pub trait SyntaxTrait {
 
 impl<'src> SyntaxTrait for Parser<'src> {
     fn expression(&mut self) {
-        println!("parse expression.");
+        self.parse_precedence(Precedence::Assignment);
     }
 
     fn consume(&mut self, tt: TokenType, msg: &str) {
@@ -80,28 +254,388 @@ impl<'src> SyntaxTrait for Parser<'src> {
     }
 }
 
+/* Statements */
+impl<'src> Parser<'src> {
+    // declarations up to the end of input
+    fn program(&mut self) {
+        self.advance();
+        while !self.matches(TokenType::Eof) {
+            self.declaration();
+        }
+    }
+
+    fn declaration(&mut self) {
+        match self.curr_token().tt {
+            TokenType::Class
+            | TokenType::Fun
+            | TokenType::Var
+            | TokenType::LeftBrace
+            | TokenType::If
+            | TokenType::While
+            | TokenType::For
+            | TokenType::Return => {
+                self.syntax_error("Not supported by the VM yet.");
+                // the rest depends on it, don't report more
+                while !self.check(TokenType::Eof) {
+                    self.advance();
+                }
+            }
+            _ => self.statement(),
+        }
+        if self.panic {
+            self.synchronize();
+        }
+    }
+
+    fn statement(&mut self) {
+        if self.matches(TokenType::Print) {
+            self.expression();
+            self.consume(TokenType::Semicolon, "Expect ';' after value.");
+            self.emit_byte(Op::Print);
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    // the expression ending the program stays on the stack
+    fn expression_statement(&mut self) {
+        self.expression();
+        if self.check(TokenType::Eof) {
+            self.has_value = true;
+            return;
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        self.emit_byte(Op::Pop);
+    }
+
+    // skip tokens until a statement boundary
+    fn synchronize(&mut self) {
+        self.panic = false;
+
+        while !self.check(TokenType::Eof) {
+            if self.prev.as_ref().map(|token| token.tt) == Some(TokenType::Semicolon) {
+                return;
+            }
+            match self.curr_token().tt {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+}
+
+/* Pratt Parsing */
+impl<'src> Parser<'src> {
+    // prefix, infix & precedence of infix
+    fn get_rule(tt: TokenType) -> (Option<ParseFn<'src>>, Option<ParseFn<'src>>, Precedence) {
+        match tt {
+            TokenType::LeftParen => (Some(Parser::grouping), Some(Parser::call), Precedence::Call),
+            TokenType::Minus => (Some(Parser::unary), Some(Parser::binary), Precedence::Term),
+            TokenType::Plus => (None, Some(Parser::binary), Precedence::Term),
+            TokenType::Slash => (None, Some(Parser::binary), Precedence::Factor),
+            TokenType::Star => (None, Some(Parser::binary), Precedence::Factor),
+            TokenType::Number => (Some(Parser::number), None, Precedence::None),
+            TokenType::Identifier => (Some(Parser::variable), None, Precedence::None),
+            TokenType::Nil | TokenType::True | TokenType::False => {
+                (Some(Parser::literal), None, Precedence::None)
+            }
+            TokenType::Bang => (Some(Parser::unary), None, Precedence::None),
+            TokenType::EqualEqual | TokenType::BangEqual => {
+                (None, Some(Parser::binary), Precedence::Equality)
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => (None, Some(Parser::binary), Precedence::Comparison),
+            // in the grammar, but not compiled yet
+            TokenType::Str | TokenType::This | TokenType::Super => {
+                (Some(Parser::unsupported), None, Precedence::None)
+            }
+            TokenType::Equal => (None, Some(Parser::unsupported), Precedence::Assignment),
+            TokenType::Or => (None, Some(Parser::unsupported), Precedence::Or),
+            TokenType::And => (None, Some(Parser::unsupported), Precedence::And),
+            TokenType::Dot => (None, Some(Parser::unsupported), Precedence::Call),
+            _ => (None, None, Precedence::None),
+        }
+    }
+
+    // valid Lox the VM can't run, the rest of the
+    // program depends on it so nothing more is reported
+    fn unsupported(&mut self) {
+        self.prev_error("Not supported by the VM yet.");
+        while !self.check(TokenType::Eof) {
+            self.advance();
+        }
+    }
+
+    fn parse_precedence(&mut self, prec: Precedence) {
+        let (prefix, _, _) = Parser::get_rule(self.curr_token().tt);
+        let prefix = match prefix {
+            Some(prefix) => prefix,
+            None => {
+                // skipped, so recovery makes progress
+                self.syntax_error("Expect expression.");
+                return self.advance();
+            }
+        };
+        self.advance();
+        prefix(self);
+
+        loop {
+            let (_, infix, infix_prec) = Parser::get_rule(self.curr_token().tt);
+            match infix {
+                Some(infix) if prec <= infix_prec => {
+                    self.advance();
+                    infix(self);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn number(&mut self) {
+        let value = self.prev_token().value.parse::<f64>().unwrap();
+        self.emit_constant(Value::from(value));
+    }
+
+    fn literal(&mut self) {
+        match self.prev_token().tt {
+            TokenType::Nil => self.emit_byte(Op::Nil),
+            TokenType::True => self.emit_byte(Op::True),
+            TokenType::False => self.emit_byte(Op::False),
+            _ => {}
+        }
+    }
+
+    // globals only, until there are locals
+    fn variable(&mut self) {
+        let token = self.prev_token();
+        let (line, col) = (token.line, token.col);
+        let name = token.value.to_string();
+
+        match self.chunk.add_name(&name) {
+            Some(idx) => {
+                let pool = self.chunk.constants.len();
+                self.ops.push((self.chunk.count, pool));
+                self.chunk
+                    .write_indexed(Op::GetGlobal, Op::GetGlobalLong, idx, line, col)
+            }
+            None => self.prev_error("Too many names in one chunk."),
+        }
+    }
+
+    fn call(&mut self) {
+        let argc = self.arguments();
+        self.emit_bytes(Op::Call, argc);
+    }
+
+    fn arguments(&mut self) -> u8 {
+        let mut argc: usize = 0;
+        if self.curr_token().tt != TokenType::RightParen {
+            loop {
+                self.expression();
+                if argc == u8::MAX as usize {
+                    self.prev_error("Can't have more than 255 arguments.");
+                }
+                argc += 1;
+                if self.curr_token().tt != TokenType::Comma {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        argc.min(u8::MAX as usize) as u8
+    }
+
+    fn grouping(&mut self) {
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after expression.");
+    }
+
+    fn unary(&mut self) {
+        let tt = self.prev_token().tt;
+        self.parse_precedence(Precedence::Unary);
+
+        match tt {
+            TokenType::Minus => {
+                self.emit_folded(Op::Negate, 1, |v| Some(Value::from(-v[0].as_number()?)))
+            }
+            TokenType::Bang => {
+                self.emit_folded(Op::Not, 1, |v| Some(Value::from(v[0].is_falsey())))
+            }
+            _ => {}
+        }
+    }
+
+    fn binary(&mut self) {
+        let tt = self.prev_token().tt;
+        let (_, _, prec) = Parser::get_rule(tt);
+        self.parse_precedence(prec.next());
+
+        // same f64 ops as the VM, so IEEE results match, and
+        // comparisons with NaN are false
+        match tt {
+            TokenType::Plus => self.emit_folded(Op::Add, 2, |v| {
+                Some(Value::from(v[0].as_number()? + v[1].as_number()?))
+            }),
+            TokenType::Minus => self.emit_folded(Op::Subtract, 2, |v| {
+                Some(Value::from(v[0].as_number()? - v[1].as_number()?))
+            }),
+            TokenType::Star => self.emit_folded(Op::Multiply, 2, |v| {
+                Some(Value::from(v[0].as_number()? * v[1].as_number()?))
+            }),
+            TokenType::Slash => self.emit_folded(Op::Divide, 2, |v| {
+                Some(Value::from(v[0].as_number()? / v[1].as_number()?))
+            }),
+            TokenType::EqualEqual => {
+                self.emit_folded(Op::Equal, 2, |v| Some(Value::from(v[0] == v[1])))
+            }
+            // `Not` on the result, folded too
+            TokenType::BangEqual => {
+                self.emit_folded(Op::Equal, 2, |v| Some(Value::from(v[0] == v[1])));
+                self.emit_folded(Op::Not, 1, |v| Some(Value::from(v[0].is_falsey())));
+            }
+            TokenType::Greater => self.emit_folded(Op::Greater, 2, |v| {
+                Some(Value::from(v[0].as_number()? > v[1].as_number()?))
+            }),
+            TokenType::GreaterEqual => self.emit_folded(Op::GreaterEqual, 2, |v| {
+                Some(Value::from(v[0].as_number()? >= v[1].as_number()?))
+            }),
+            TokenType::Less => self.emit_folded(Op::Less, 2, |v| {
+                Some(Value::from(v[0].as_number()? < v[1].as_number()?))
+            }),
+            TokenType::LessEqual => self.emit_folded(Op::LessEqual, 2, |v| {
+                Some(Value::from(v[0].as_number()? <= v[1].as_number()?))
+            }),
+            _ => {}
+        }
+    }
+}
+
 /* Error Report */
 trait ErrorReportTrait {
-	fn lexic_error(&mut self);
-	fn syntax_error(&mut self, msg: &str);
+    fn lexic_error(&mut self);
+    fn syntax_error(&mut self, msg: &str);
+    fn prev_error(&mut self, msg: &str);
+    fn error_at(&mut self, where_: &str, msg: &str);
+    fn report(&mut self, span: Span, line: usize, col: usize, where_: &str, msg: &str);
 }
 
 impl<'src> ErrorReportTrait for Parser<'src> {
-
+    // scanner puts the error message in token value
     fn lexic_error(&mut self) {
-        self.syntax_error("Unexpected token.");
+        let msg = self.curr_token().value.to_string();
+        self.error_at("", &msg);
     }
 
     fn syntax_error(&mut self, msg: &str) {
+        let token = self.curr_token();
+        let where_ = match token.tt {
+            TokenType::Eof => String::from(" at end"),
+            _ => format!(" at '{}'", token.value),
+        };
+        self.error_at(&where_, msg);
+    }
+
+    // error at the token just consumed
+    fn prev_error(&mut self, msg: &str) {
+        let token = self.prev_token();
+        let (span, line, col) = (token.span, token.line, token.col);
+        let where_ = format!(" at '{}'", token.value);
+        self.report(span, line, col, &where_, msg);
+    }
+
+    fn error_at(&mut self, where_: &str, msg: &str) {
+        let token = self.curr_token();
+        let (span, line, col) = (token.span, token.line, token.col);
+        self.report(span, line, col, where_, msg);
+    }
+
+    fn report(&mut self, span: Span, line: usize, col: usize, where_: &str, msg: &str) {
         if self.panic {
             return;
         }
         self.panic = true;
 
-        let token = self.curr_token();
-        println!("line[{}] error at token: {:?} {}", token.line, token.tt, token.value);
-        println!("{}", msg);
+        let err = format!("[line {}:{}] Error{}: {}", line, col, where_, msg);
+        self.errors.push(err);
+        self.diagnostics.push(Diagnostic::new(msg, span, line, col));
 
         self.had_error = true;
     }
 }
+
+/* Compile */
+
+// the chunk, or the errors without printing them
+pub fn compile_chunk(source: &str, fold: bool) -> Result<Chunk, Vec<String>> {
+    let mut scanner = Scanner::new(source);
+    let mut parser = Parser::new(&mut scanner);
+    parser.fold = fold;
+
+    parser.program();
+
+    if parser.had_error {
+        return Err(std::mem::take(&mut parser.errors));
+    }
+    let mut bytes = Chunk::new();
+    parser.end_compile(&mut bytes);
+    Ok(bytes)
+}
+
+// errors of compiling `source`, located for tools
+pub fn compile_diagnostics(source: &str) -> Vec<Diagnostic> {
+    let mut scanner = Scanner::new(source);
+    let mut parser = Parser::new(&mut scanner);
+
+    parser.program();
+    parser.diagnostics
+}
+
+// without folding every line keeps its code, for debuggers
+pub fn compile_with(source: &str, bytes: &mut Chunk, fold: bool) -> bool {
+    if DEBUG_LEXER {
+        return dump_tokens(source);
+    }
+    match compile_chunk(source, fold) {
+        Ok(chunk) => {
+            *bytes = chunk;
+            true
+        }
+        Err(errors) => {
+            for err in errors.iter() {
+                eprintln!("{}", err);
+            }
+            false
+        }
+    }
+}
+
+// print tokens by line, false on a lexical error
+fn dump_tokens(source: &str) -> bool {
+    let mut line = usize::MAX;
+    let mut no_err = true;
+
+    for token in Scanner::new(source) {
+        if token.line != line {
+            print!("[line {:4}] ", token.line);
+            line = token.line;
+        } else {
+            print!("          | ");
+        }
+        println!("{:?} {}", token.tt, token.value);
+
+        if let TokenType::Error = token.tt {
+            no_err = false;
+        }
+    }
+    no_err
+}
@@ -0,0 +1,113 @@
+// Conversions between Rust types & Lox values, used to pass
+// arguments to typed natives (`VirtualMachine::define_fn`) and
+// return their results.
+//
+//   f64     number
+//   bool    boolean
+//   ()      nil
+//   Option  nil is None, anything else converts as T
+//   Value   as is
+
+use crate::chunk::Value;
+
+pub trait FromLox: Sized {
+    // None if `value` isn't of this type
+    fn from_lox(value: Value) -> Option<Self>;
+
+    // for errors, e.g. "a number"
+    fn expected() -> String;
+}
+
+pub trait IntoLox {
+    fn into_lox(self) -> Value;
+}
+
+impl FromLox for Value {
+    fn from_lox(value: Value) -> Option<Value> {
+        Some(value)
+    }
+
+    fn expected() -> String {
+        String::from("a value")
+    }
+}
+
+impl IntoLox for Value {
+    fn into_lox(self) -> Value {
+        self
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: Value) -> Option<f64> {
+        value.as_number()
+    }
+
+    fn expected() -> String {
+        String::from("a number")
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: Value) -> Option<bool> {
+        value.as_bool()
+    }
+
+    fn expected() -> String {
+        String::from("a boolean")
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromLox for () {
+    fn from_lox(value: Value) -> Option<()> {
+        if value.is_nil() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn expected() -> String {
+        String::from("nil")
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Value {
+        Value::nil()
+    }
+}
+
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: Value) -> Option<Option<T>> {
+        if value.is_nil() {
+            return Some(None);
+        }
+        T::from_lox(value).map(Some)
+    }
+
+    fn expected() -> String {
+        format!("{} or nil", T::expected())
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Value {
+        match self {
+            Some(val) => val.into_lox(),
+            None => Value::nil(),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// Debug adapter over stdio, speaking the protocol in
+//   https://microsoft.github.io/debug-adapter-protocol/
+//
+// The VM runs on the same thread, when it stops the adapter
+// blocks in the VM hook and serves requests until resumed.
+//
+// There is one thread, and one frame (the script) until the
+// VM supports function calls, so the value stack & globals are
+// what can be inspected. Stepping out & locals wait for calls.
+
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::chunk::*;
+use crate::compiler::compile_with;
+use crate::stepper::*;
+use crate::transport::*;
+use crate::vm::*;
+
+const THREAD_ID: u64 = 1;
+const FRAME_ID: u64 = 0;
+// variablesReference of the value stack & globals
+const STACK_REF: u64 = 1;
+const GLOBALS_REF: u64 = 2;
+
+/// Serve a debug session until `disconnect` or end of input.
+pub fn serve<R: BufRead, W: Write>(input: R, output: W) -> std::io::Result<()> {
+    let mut adapter = Adapter {
+        input,
+        output,
+        seq: 0,
+        program: String::new(),
+        chunk: None,
+        stepper: Stepper::new(false),
+        lines: Vec::new(),
+        error: None,
+        done: false,
+    };
+
+    while let Some(req) = adapter.read()? {
+        adapter.handle(&req, None)?;
+        if adapter.done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct Adapter<R, W> {
+    input: R,
+    output: W,
+    seq: u64,
+    program: String,
+    chunk: Option<Chunk>,
+    stepper: Stepper,
+    // breakpoints asked before launch
+    lines: Vec<usize>,
+    // io error in the VM hook
+    error: Option<std::io::Error>,
+    done: bool,
+}
+
+impl<R: BufRead, W: Write> Adapter<R, W> {
+    // returns how to resume if the VM is stopped
+    fn handle(
+        &mut self,
+        req: &Value,
+        stopped: Option<(&VirtualMachine, &Chunk)>,
+    ) -> std::io::Result<Option<Resume>> {
+        let command = req["command"].as_str().unwrap_or("");
+        let args = &req["arguments"];
+
+        let mut resume = None;
+        let body = match command {
+            "initialize" => {
+                let body = json!({ "supportsConfigurationDoneRequest": true });
+                self.respond(req, Ok(body))?;
+                return self.event("initialized", json!({})).map(|_| None);
+            }
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args, stopped.map(|(_, c)| c))),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                self.respond(req, Ok(json!({})))?;
+                return self.start().map(|_| None);
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+
+            "stackTrace" => match stopped {
+                Some((vm, bytes)) => Ok(self.stack_trace(vm, bytes)),
+                None => Err("Not stopped."),
+            },
+            "scopes" => Ok(json!({ "scopes": [{
+                "name": "Stack",
+                "variablesReference": STACK_REF,
+                "expensive": false,
+            }, {
+                "name": "Globals",
+                "variablesReference": GLOBALS_REF,
+                "expensive": false,
+            }]})),
+            "variables" => match stopped {
+                Some((vm, _)) => Ok(variables(vm, args["variablesReference"].as_u64())),
+                None => Ok(json!({ "variables": [] })),
+            },
+
+            "continue" | "next" | "stepIn" => match stopped {
+                Some((vm, bytes)) => {
+                    let mode = match command {
+                        "next" => StepMode::Over,
+                        "stepIn" => StepMode::In,
+                        _ => StepMode::Continue,
+                    };
+                    self.stepper.resume(mode, vm, bytes);
+                    resume = Some(Resume::Continue);
+                    Ok(json!({ "allThreadsContinued": true }))
+                }
+                None => Err("Not stopped."),
+            },
+            "disconnect" | "terminate" => {
+                self.done = true;
+                resume = Some(Resume::Terminate);
+                Ok(json!({}))
+            }
+            _ => Err("Unsupported request."),
+        };
+        self.respond(req, body)?;
+        Ok(resume)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, &'static str> {
+        let program = args["program"].as_str().ok_or("Missing 'program'.")?;
+        let source = std::fs::read_to_string(program).map_err(|_| "Cannot read program.")?;
+
+        let mut chunk = Chunk::new();
+        if !compile_with(&source, &mut chunk, false) {
+            return Err("Compile error.");
+        }
+        self.program = String::from(program);
+        self.stepper = Stepper::new(args["stopOnEntry"].as_bool().unwrap_or(false));
+        self.stepper.set_breakpoints(&chunk, &self.lines);
+        self.chunk = Some(chunk);
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value, stopped: Option<&Chunk>) -> Value {
+        let lines: Vec<usize> = match args["breakpoints"].as_array() {
+            Some(bps) => bps
+                .iter()
+                .filter_map(|bp| bp["line"].as_u64())
+                .map(|line| line as usize)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        // chunk is lent to the VM while running
+        let bytes = match stopped {
+            Some(bytes) => Some(bytes),
+            None => self.chunk.as_ref(),
+        };
+        let actual = match bytes {
+            Some(bytes) => self.stepper.set_breakpoints(bytes, &lines),
+            // not launched, verified when launching
+            None => lines.iter().map(|line| Some(*line)).collect(),
+        };
+        self.lines = lines;
+
+        let bps: Vec<Value> = actual
+            .iter()
+            .map(|line| match line {
+                Some(line) => json!({ "verified": true, "line": line }),
+                None => json!({ "verified": false, "message": "No code at or after this line." }),
+            })
+            .collect();
+        json!({ "breakpoints": bps })
+    }
+
+    fn stack_trace(&self, vm: &VirtualMachine, bytes: &Chunk) -> Value {
+        let (line, col) = bytes.get_position(vm.ip());
+        let name = std::path::Path::new(&self.program)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+
+        json!({
+            "stackFrames": [{
+                "id": FRAME_ID,
+                "name": "script",
+                "line": line,
+                "column": col.max(1),
+                "source": { "name": name, "path": self.program },
+            }],
+            "totalFrames": 1,
+        })
+    }
+
+    // run the launched program until it ends or is terminated
+    fn start(&mut self) -> std::io::Result<()> {
+        let chunk = match self.chunk.take() {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
+        let status = VirtualMachine::new().debug(&chunk, self);
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.chunk = Some(chunk);
+
+        let code = match status {
+            InterpretResult::OK => 0,
+            InterpretResult::CompileError => 65,
+            InterpretResult::RuntimeError => 70,
+        };
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    // block until resumed
+    fn pause(
+        &mut self,
+        vm: &VirtualMachine,
+        bytes: &Chunk,
+        reason: StopReason,
+    ) -> std::io::Result<Resume> {
+        let reason = match reason {
+            StopReason::Entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+        };
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID }),
+        )?;
+
+        while let Some(req) = self.read()? {
+            if let Some(resume) = self.handle(&req, Some((vm, bytes)))? {
+                return Ok(resume);
+            }
+        }
+        Ok(Resume::Terminate)
+    }
+
+    fn read(&mut self) -> std::io::Result<Option<Value>> {
+        read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut msg: Value) -> std::io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+
+        write_message(&mut self.output, &msg)
+    }
+
+    fn respond(&mut self, req: &Value, body: Result<Value, &str>) -> std::io::Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => msg["body"] = body,
+            Err(message) => msg["message"] = json!(message),
+        }
+        self.send(msg)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> std::io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+// values of a scope, globals by name
+fn variables(vm: &VirtualMachine, reference: Option<u64>) -> Value {
+    let vars: Vec<(String, String)> = match reference {
+        Some(STACK_REF) => vm
+            .stack()
+            .iter()
+            .enumerate()
+            .map(|(i, val)| (format!("[{}]", i), val.to_string()))
+            .collect(),
+        Some(GLOBALS_REF) => {
+            let mut globals: Vec<(String, String)> = vm
+                .globals()
+                .iter()
+                .map(|(name, val)| (name.clone(), val.to_string()))
+                .collect();
+            globals.sort();
+            globals
+        }
+        _ => Vec::new(),
+    };
+
+    let vars: Vec<Value> = vars
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value, "variablesReference": 0 }))
+        .collect();
+    json!({ "variables": vars })
+}
+
+impl<R: BufRead, W: Write> DebugHook for Adapter<R, W> {
+    fn before_op(&mut self, vm: &VirtualMachine, bytes: &Chunk) -> Resume {
+        let reason = match self.stepper.should_stop(vm, bytes) {
+            Some(reason) => reason,
+            None => return Resume::Continue,
+        };
+        match self.pause(vm, bytes, reason) {
+            Ok(resume) => resume,
+            Err(err) => {
+                self.error = Some(err);
+                Resume::Terminate
+            }
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        self.output("stdout", text);
+    }
+
+    fn error(&mut self, text: &str) {
+        self.output("stderr", text);
+    }
+}
+
+impl<R: BufRead, W: Write> Adapter<R, W> {
+    // a line of program output, shown in the client's console
+    fn output(&mut self, category: &str, text: &str) {
+        let output = format!("{}\n", text);
+        if let Err(err) = self.event("output", json!({ "category": category, "output": output })) {
+            self.error = Some(err);
+        }
+    }
+}
@@ -1,14 +1,71 @@
+use std::fmt;
+
 use crate::chunk::*;
 
+// constant pool usage of a chunk
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PoolStats {
+    pub constants: usize,
+    // instructions reading a constant
+    pub loads: usize,
+    pub long_loads: usize,
+}
+
+impl PoolStats {
+    pub fn new(bytes: &Chunk) -> PoolStats {
+        let mut stats = PoolStats {
+            constants: bytes.constants.len(),
+            ..PoolStats::default()
+        };
+        let mut ip = 0;
+        while let Some(ins) = bytes.decode(ip) {
+            if ins.constant().is_some() {
+                stats.loads += 1;
+            }
+            if let Instruction::ConstantLong(_) = ins {
+                stats.long_loads += 1;
+            }
+            ip += ins.size();
+        }
+        stats
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} constants, {} loads ({} long)",
+            self.constants, self.loads, self.long_loads
+        )
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Disassembler {
     ip: usize,
+    // op to mark, where a debugger is stopped
+    current: Option<usize>,
 }
 
 #[allow(dead_code)]
 impl Disassembler {
     pub fn new() -> Self {
-        Disassembler { ip: 0 }
+        Disassembler {
+            ip: 0,
+            current: None,
+        }
+    }
+
+    // go on from the op at `ip`, e.g. where the VM is
+    pub fn seek(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    // mark the op at `ip` with `=>`
+    pub fn highlight(mut self, ip: usize) -> Self {
+        self.current = Some(ip);
+        self
     }
 
     pub fn disassemble(mut self, bytes: &Chunk, name: &str) {
@@ -22,51 +79,92 @@ impl Disassembler {
         while self.ip < bytes.count {
             self.disassemble_op(bytes);
         }
+        println!("== pool: {} ==", PoolStats::new(bytes));
+    }
+
+    // all ops, one line each
+    pub fn listing(mut self, bytes: &Chunk) -> Vec<String> {
+        let mut ops = Vec::new();
+        while self.ip < bytes.count {
+            ops.push(self.format_op(bytes));
+        }
+        ops
     }
 
     pub fn disassemble_op(&mut self, bytes: &Chunk) {
-        print!("{:04} ", self.ip);
+        println!("{}", self.format_op(bytes));
+    }
 
-        // print line number
-        let line = line_info!(self, bytes);
-        print!("{}  ", line);
+    // one line per op, without newline
+    pub fn format_op(&mut self, bytes: &Chunk) -> String {
+        let mut text = match self.current {
+            Some(ip) if ip == self.ip => String::from("=> "),
+            Some(_) => String::from("   "),
+            None => String::new(),
+        };
+        text += &format!("{:04} ", self.ip);
 
-        let op = &bytes.code[self.ip];
-        match op {
-            Op::Return => {
-                println!("OP_RETURN");
-                self.ip += 1;
-            }
-            Op::Constant => {
-                let (idx, val) = bytes.get_constant(self.ip);
+        // line number
+        text += &line_info!(self, bytes);
+        text += "  ";
 
-                println!("OP_CONST  {:04} {}", idx, val);
-                self.ip += 2;
-            }
-            Op::Negate => {
-                println!("OP_NEG");
-                self.ip += 1;
-            }
-            Op::Add => {
-                println!("OP_ADD");
-                self.ip += 1;
-            }
-            Op::Subtract => {
-                println!("OP_SUB");
-                self.ip += 1;
-            }
-            Op::Multiply => {
-                println!("OP_MUL");
+        let ins = match bytes.decode(self.ip) {
+            Some(ins) => ins,
+            None => {
+                text += &format!("OP_UNK    {}", bytes.code[self.ip]);
                 self.ip += 1;
+                return text;
             }
-            Op::Divide => {
-                println!("OP_DIV");
-                self.ip += 1;
+        };
+        self.ip += ins.size();
+
+        match ins {
+            Instruction::Return => text += "OP_RETURN",
+            Instruction::Constant(idx) => text += &constant("OP_CONST", idx as usize, bytes),
+            Instruction::ConstantLong(idx) => text += &constant("OP_CONST_LONG", idx, bytes),
+            Instruction::Negate => text += "OP_NEG",
+            Instruction::Add => text += "OP_ADD",
+            Instruction::Subtract => text += "OP_SUB",
+            Instruction::Multiply => text += "OP_MUL",
+            Instruction::Divide => text += "OP_DIV",
+            Instruction::Nil => text += "OP_NIL",
+            Instruction::AddConstant(idx) => text += &constant("OP_ADD_C", idx as usize, bytes),
+            Instruction::SubtractConstant(idx) => {
+                text += &constant("OP_SUB_C", idx as usize, bytes)
             }
-            _ => {
-                println!("OP_UNK");
-                self.ip += 1;
+            Instruction::MultiplyConstant(idx) => {
+                text += &constant("OP_MUL_C", idx as usize, bytes)
             }
+            Instruction::DivideConstant(idx) => text += &constant("OP_DIV_C", idx as usize, bytes),
+            Instruction::GetGlobal(idx) => text += &name("OP_GET_GLOBAL", idx as usize, bytes),
+            Instruction::GetGlobalLong(idx) => text += &name("OP_GET_GLOBAL_LONG", idx, bytes),
+            Instruction::Call(argc) => text += &format!("{:<9} {}", "OP_CALL", argc),
+            Instruction::Print => text += "OP_PRINT",
+            Instruction::Pop => text += "OP_POP",
+            Instruction::True => text += "OP_TRUE",
+            Instruction::False => text += "OP_FALSE",
+            Instruction::Not => text += "OP_NOT",
+            Instruction::Equal => text += "OP_EQUAL",
+            Instruction::Greater => text += "OP_GREATER",
+            Instruction::GreaterEqual => text += "OP_GREATER_EQ",
+            Instruction::Less => text += "OP_LESS",
+            Instruction::LessEqual => text += "OP_LESS_EQ",
         }
+        text
+    }
+}
+
+fn constant(name: &str, idx: usize, bytes: &Chunk) -> String {
+    // may be out of range before verifying
+    match bytes.constants.get(idx) {
+        Some(val) => format!("{:<9} {:04} {}", name, idx, val),
+        None => format!("{:<9} {:04} ?", name, idx),
+    }
+}
+
+fn name(op: &str, idx: usize, bytes: &Chunk) -> String {
+    match bytes.names.get(idx) {
+        Some(name) => format!("{:<9} {:04} {}", op, idx, name),
+        None => format!("{:<9} {:04} ?", op, idx),
     }
 }
@@ -0,0 +1,188 @@
+// Command line debugger, gdb-like:
+//
+//   $ rlox debug script.lox
+//   (rlox) break 3
+//   (rlox) continue
+//
+// Stops before the first op, commands are read whenever the
+// VM is paused, see `HELP`.
+
+use std::io::{BufRead, Write};
+
+use crate::ast::parse;
+use crate::chunk::*;
+use crate::compiler::{compile_chunk, compile_with};
+use crate::debug::Disassembler;
+use crate::stepper::*;
+use crate::vm::*;
+
+const PROMPT: &str = "(rlox) ";
+
+const HELP: &str = "\
+break <line>  stop at line, or the first line with code after it
+step          run to the next line, entering calls
+next          run to the next line, stepping over calls
+continue      run to the next breakpoint
+stack         show call frames and the value stack
+disasm        disassemble, marking the current instruction
+print <expr>  evaluate an expression
+quit          stop the program";
+
+/// Debug `source`, reading commands from `input`. Program
+/// output & debugger messages go to `output`.
+pub fn debug<R: BufRead, W: Write>(source: &str, input: R, output: W) -> InterpretResult {
+    debug_with(VirtualMachine::new(), source, input, output)
+}
+
+/// Like `debug`, on a VM with the host's natives & globals.
+pub fn debug_with<R: BufRead, W: Write>(
+    vm: VirtualMachine,
+    source: &str,
+    input: R,
+    output: W,
+) -> InterpretResult {
+    let mut chunk = Chunk::new();
+    if !compile_with(source, &mut chunk, false) {
+        return InterpretResult::CompileError;
+    }
+
+    let mut debugger = Debugger {
+        input,
+        output,
+        source: source.lines().map(String::from).collect(),
+        stepper: Stepper::new(true),
+        breakpoints: Vec::new(),
+    };
+    vm.debug(&chunk, &mut debugger)
+}
+
+struct Debugger<R, W> {
+    input: R,
+    output: W,
+    source: Vec<String>,
+    stepper: Stepper,
+    // lines asked for, in order
+    breakpoints: Vec<usize>,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    // serve commands until resumed, io errors end the session
+    fn pause(&mut self, vm: &VirtualMachine, bytes: &Chunk) -> std::io::Result<Resume> {
+        let line = bytes.get_line(vm.ip());
+        let source = &self.source;
+        let text = line.checked_sub(1).and_then(|i| source.get(i));
+        let text = text.map_or("", String::as_str);
+        writeln!(self.output, "{:4} | {}", line, text)?;
+
+        loop {
+            write!(self.output, "{}", PROMPT)?;
+            self.output.flush()?;
+
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                return Ok(Resume::Terminate);
+            }
+            let command = command.trim();
+            let (name, arg) = match command.split_once(' ') {
+                Some((name, arg)) => (name, arg.trim()),
+                None => (command, ""),
+            };
+
+            let mode = match name {
+                "" => continue,
+                "step" | "s" => StepMode::In,
+                "next" | "n" => StepMode::Over,
+                "continue" | "c" => StepMode::Continue,
+                "quit" | "q" => return Ok(Resume::Terminate),
+
+                "break" | "b" => {
+                    self.set_breakpoint(arg, bytes)?;
+                    continue;
+                }
+                "stack" | "bt" => {
+                    writeln!(self.output, "#0 script at line {}", line)?;
+                    for (i, val) in vm.stack().iter().enumerate().rev() {
+                        writeln!(self.output, "  [{}] {}", i, val)?;
+                    }
+                    continue;
+                }
+                "disasm" => {
+                    let listing = Disassembler::new().highlight(vm.ip()).listing(bytes);
+                    for op in listing.iter() {
+                        writeln!(self.output, "{}", op)?;
+                    }
+                    continue;
+                }
+                "print" | "p" => {
+                    self.evaluate(arg, vm)?;
+                    continue;
+                }
+                "help" | "h" => {
+                    writeln!(self.output, "{}", HELP)?;
+                    continue;
+                }
+                _ => {
+                    writeln!(self.output, "Unknown command '{}', try 'help'.", name)?;
+                    continue;
+                }
+            };
+            self.stepper.resume(mode, vm, bytes);
+            return Ok(Resume::Continue);
+        }
+    }
+
+    // evaluate an expression with the paused program's globals &
+    // natives, errors are shown like its output
+    fn evaluate(&mut self, expr: &str, vm: &VirtualMachine) -> std::io::Result<()> {
+        let chunk = match compile_chunk(expr, true) {
+            Ok(chunk) => chunk,
+            Err(errors) => {
+                for err in errors.iter() {
+                    writeln!(self.output, "{}", err)?;
+                }
+                return Ok(());
+            }
+        };
+        // statements would print past the debugger
+        match parse(expr) {
+            Ok(program) if program.decls.is_empty() && program.value.is_some() => {}
+            _ => return writeln!(self.output, "Expect an expression."),
+        }
+
+        match vm.fork().execute(&chunk) {
+            Ok(val) => writeln!(self.output, "{}", val),
+            Err(err) => writeln!(self.output, "{}", err.message),
+        }
+    }
+
+    fn set_breakpoint(&mut self, arg: &str, bytes: &Chunk) -> std::io::Result<()> {
+        let line = match arg.parse::<usize>() {
+            Ok(line) if line > 0 => line,
+            _ => return writeln!(self.output, "Expect a line number."),
+        };
+        self.breakpoints.push(line);
+
+        let actual = self.stepper.set_breakpoints(bytes, &self.breakpoints);
+        match actual.last() {
+            Some(Some(actual)) => writeln!(self.output, "Breakpoint at line {}.", actual),
+            _ => {
+                self.breakpoints.pop();
+                writeln!(self.output, "No code at or after line {}.", line)
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHook for Debugger<R, W> {
+    fn before_op(&mut self, vm: &VirtualMachine, bytes: &Chunk) -> Resume {
+        if self.stepper.should_stop(vm, bytes).is_none() {
+            return Resume::Continue;
+        }
+        self.pause(vm, bytes).unwrap_or(Resume::Terminate)
+    }
+
+    fn print(&mut self, text: &str) {
+        // nowhere else to report to
+        let _ = writeln!(self.output, "{}", text);
+    }
+}
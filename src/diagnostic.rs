@@ -0,0 +1,76 @@
+use std::fmt;
+
+use crate::lexer::{Span, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    // never stops compiling or running
+    Warning,
+}
+
+// an error reported with its location,
+// shared by tooling which can't print directly
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // lint code of warnings, e.g. "W001"
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Span,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Diagnostic {
+    pub fn new(message: &str, span: Span, line: usize, col: usize) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: String::from(message),
+            span,
+            line,
+            col,
+        }
+    }
+
+    pub fn at(token: &Token, message: &str) -> Diagnostic {
+        Diagnostic::new(message, token.span, token.line, token.col)
+    }
+
+    pub fn warning(
+        code: &'static str,
+        message: &str,
+        span: Span,
+        line: usize,
+        col: usize,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: Some(code),
+            ..Diagnostic::new(message, span, line, col)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.severity, self.code) {
+            (Severity::Warning, Some(code)) => write!(
+                f,
+                "[line {}:{}] Warning {}: {}",
+                self.line, self.col, code, self.message
+            ),
+            (Severity::Warning, None) => write!(
+                f,
+                "[line {}:{}] Warning: {}",
+                self.line, self.col, self.message
+            ),
+            _ => write!(
+                f,
+                "[line {}:{}] Error: {}",
+                self.line, self.col, self.message
+            ),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Embedding API, re-exported at the crate root. `Vm` compiles &
+// runs source on a `VirtualMachine` that keeps its globals
+// between calls.
+
+use std::fmt;
+
+use crate::chunk::*;
+use crate::compiler::compile_chunk;
+use crate::convert::IntoLox;
+use crate::native::*;
+use crate::optimize::optimize;
+use crate::verify::{verify, VerifyError};
+use crate::vm::*;
+
+/// Settings of a [`Vm`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Config {
+    /// Optimization level as `-O<level>`, 0 to `optimize::MAX_LEVEL`.
+    pub opt_level: u8,
+    /// Stack & call depth limits.
+    pub limits: Limits,
+}
+
+/// An error from compiling or running Lox.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    /// Syntax errors, one message each, e.g.
+    /// `[line 1:4] Error at end: Expect expression.`
+    Compile(Vec<String>),
+    /// A chunk that failed verification.
+    Invalid(VerifyError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoxError::Compile(errors) => write!(f, "{}", errors.join("\n")),
+            LoxError::Invalid(err) => write!(f, "Invalid bytecode: {}", err),
+            LoxError::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+impl From<RuntimeError> for LoxError {
+    fn from(err: RuntimeError) -> LoxError {
+        LoxError::Runtime(err)
+    }
+}
+
+/// Compile `source` to a chunk without running it.
+pub fn compile(source: &str) -> Result<Chunk, LoxError> {
+    compile_chunk(source, true).map_err(LoxError::Compile)
+}
+
+/// A Lox interpreter, see the crate docs for an example.
+#[derive(Debug)]
+pub struct Vm {
+    vm: VirtualMachine,
+    config: Config,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new(Config::default())
+    }
+}
+
+impl Vm {
+    pub fn new(config: Config) -> Vm {
+        Vm {
+            vm: VirtualMachine::with_limits(config.limits),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Compile & run `source`, returning the value of the script.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let bytes = compile(source)?;
+        let bytes = optimize(&bytes, self.config.opt_level);
+        Ok(self.vm.execute_verified(&bytes)?)
+    }
+
+    /// Run a compiled chunk, e.g. from `bytecode::deserialize`.
+    /// It's verified first, as it may not come from the compiler.
+    pub fn run(&mut self, bytes: &Chunk) -> Result<Value, LoxError> {
+        verify(bytes).map_err(LoxError::Invalid)?;
+        let bytes = optimize(bytes, self.config.opt_level);
+        Ok(self.vm.execute_verified(&bytes)?)
+    }
+
+    /// Call the global function `name`. Lox can't declare functions
+    /// yet, so this only reaches natives defined from Rust.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, LoxError> {
+        let callee = match self.vm.global(name) {
+            Some(callee) => callee,
+            None => {
+                let msg = format!("Undefined variable '{}'.", name);
+                return Err(LoxError::Runtime(RuntimeError::new(&msg)));
+            }
+        };
+        Ok(self.vm.call_value(callee, args)?)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.vm.global(name)
+    }
+
+    /// Define or replace the global `name`.
+    pub fn set_global<V: IntoLox>(&mut self, name: &str, value: V) {
+        self.vm.set_global(name, value.into_lox());
+    }
+
+    /// Define a native taking `&mut VirtualMachine` & the
+    /// arguments, see [`VirtualMachine::define_native`].
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        self.vm.define_native(name, arity, function);
+    }
+
+    /// Define a typed closure as a native, e.g.
+    /// `vm.define_fn("add", |a: f64, b: f64| a + b)`.
+    pub fn define_fn<Args, F: IntoNative<Args>>(&mut self, name: &str, function: F) {
+        self.vm.define_fn(name, function);
+    }
+}
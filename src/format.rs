@@ -0,0 +1,288 @@
+// Canonical source formatter.
+//
+// It works on the lossless token stream (see `Scanner::with_trivia`)
+// instead of the syntax tree, so comments stay where they were.
+// Source is only parsed to refuse formatting broken code.
+//
+// Layout rules:
+//   * 2 spaces indentation, one statement per line
+//   * `{` stays on the line of its header, `} else {` is joined
+//   * binary operators are surrounded by spaces, unary ones are not
+//   * at most one blank line between statements
+//   * lines longer than `MAX_WIDTH` are wrapped after commas
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::*;
+
+const INDENT: &str = "  ";
+const MAX_WIDTH: usize = 100;
+
+/// Format the source, or return its syntax errors.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    ast::parse(source)?;
+
+    let mut formatter = Formatter::new();
+    for token in Scanner::with_trivia(source) {
+        formatter.token(&token);
+    }
+    Ok(formatter.finish())
+}
+
+#[derive(Debug, Default)]
+struct Piece {
+    text: String,
+    space: bool,
+    // paren depth of the token
+    depth: usize,
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    indent: usize,
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Default)]
+struct Formatter {
+    lines: Vec<Line>,
+    line: Line,
+    indent: usize,
+    parens: usize,
+    prev: Option<TokenType>,
+    // next token starts a new line
+    newline: bool,
+    // line broken by a comment in the middle of a statement
+    continued: bool,
+}
+
+impl Formatter {
+    fn new() -> Formatter {
+        Formatter {
+            newline: true,
+            ..Default::default()
+        }
+    }
+
+    fn token(&mut self, token: &Token) {
+        let blank = self.trivia(token);
+
+        if token.tt == TokenType::Eof {
+            return;
+        }
+        if token.tt == TokenType::RightBrace {
+            self.indent = self.indent.saturating_sub(1);
+        }
+
+        let newline = match (self.prev, token.tt) {
+            // empty block
+            (Some(TokenType::LeftBrace), TokenType::RightBrace) => false,
+            (_, TokenType::RightBrace) => true,
+            (Some(TokenType::RightBrace), TokenType::Else) => false,
+            _ => self.newline,
+        };
+        if newline {
+            self.end_line();
+            let after_open = self.prev == Some(TokenType::LeftBrace);
+            if blank && !after_open && token.tt != TokenType::RightBrace {
+                self.blank_line();
+            }
+        }
+        let space = !self.line.pieces.is_empty() && self.space_before(token.tt);
+        self.push(token.lexeme, space);
+        self.continued = false;
+
+        match token.tt {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => self.parens = self.parens.saturating_sub(1),
+            TokenType::LeftBrace => self.indent += 1,
+            _ => {}
+        }
+        self.newline = match token.tt {
+            TokenType::Semicolon => self.parens == 0,
+            TokenType::LeftBrace | TokenType::RightBrace => true,
+            _ => false,
+        };
+        self.prev = Some(token.tt);
+    }
+
+    // emit comments, return if there's a blank line before the token
+    fn trivia(&mut self, token: &Token) -> bool {
+        let mut newlines = 0;
+
+        for trivia in token.leading.iter() {
+            match trivia.kind {
+                TriviaKind::Newline => newlines += 1,
+                TriviaKind::Whitespace => {}
+                TriviaKind::Comment => {
+                    let text = trivia.text.trim_end();
+
+                    if newlines == 0 && !self.line.pieces.is_empty() {
+                        // trailing comment
+                        self.push(text, true);
+                    } else {
+                        self.end_line();
+                        self.continued = !self.newline;
+                        if newlines > 1 && self.prev != Some(TokenType::LeftBrace) {
+                            self.blank_line();
+                        }
+                        // comment before `}` is still inside the block
+                        self.push(text, false);
+                    }
+                    // comment ends the line
+                    self.end_line();
+                    self.continued = !self.newline;
+                    newlines = 0;
+                }
+            }
+        }
+        newlines > 1
+    }
+
+    fn space_before(&self, tt: TokenType) -> bool {
+        let prev = match self.prev {
+            Some(prev) => prev,
+            None => return false,
+        };
+        match (prev, tt) {
+            (_, TokenType::RightParen)
+            | (_, TokenType::Comma)
+            | (_, TokenType::Semicolon)
+            | (_, TokenType::Dot)
+            | (TokenType::LeftParen, _)
+            | (TokenType::Dot, _)
+            | (TokenType::Bang, _)
+            | (TokenType::LeftBrace, TokenType::RightBrace) => false,
+            // call or function declaration
+            (TokenType::Identifier, TokenType::LeftParen)
+            | (TokenType::RightParen, TokenType::LeftParen)
+            | (TokenType::This, TokenType::LeftParen)
+            | (TokenType::Super, TokenType::LeftParen) => false,
+            (TokenType::Minus, _) => !self.unary_minus(),
+            _ => true,
+        }
+    }
+
+    // the last `-` is unary unless it follows an operand
+    fn unary_minus(&self) -> bool {
+        let before = self.line.pieces.len().checked_sub(2);
+        let before = before.map(|i| self.line.pieces[i].text.as_str());
+
+        match before {
+            None => true,
+            Some(text) => !is_operand_end(text),
+        }
+    }
+
+    fn push(&mut self, text: &str, space: bool) {
+        if self.line.pieces.is_empty() {
+            self.line.indent = self.indent + self.continued as usize;
+        }
+        self.line.pieces.push(Piece {
+            text: String::from(text),
+            space,
+            depth: self.parens,
+        });
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.pieces.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.lines.push(line);
+        }
+    }
+
+    fn blank_line(&mut self) {
+        if let Some(last) = self.lines.last() {
+            if !last.pieces.is_empty() {
+                self.lines.push(Line::default());
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.end_line();
+
+        let mut text = String::new();
+        for line in self.lines.iter() {
+            render(line, &mut text);
+        }
+        text
+    }
+}
+
+// literal, name or closing paren, i.e. a following `-` is binary
+fn is_operand_end(text: &str) -> bool {
+    match text {
+        ")" | "nil" | "true" | "false" | "this" => true,
+        _ => {
+            let first = text.as_bytes()[0];
+            let keyword = matches!(
+                text,
+                "and"
+                    | "class"
+                    | "else"
+                    | "for"
+                    | "fun"
+                    | "if"
+                    | "or"
+                    | "print"
+                    | "return"
+                    | "super"
+                    | "var"
+                    | "while"
+            );
+            !keyword && (first == b'"' || first == b'_' || first.is_ascii_alphanumeric())
+        }
+    }
+}
+
+fn render(line: &Line, text: &mut String) {
+    let mut out = INDENT.repeat(line.indent);
+    let start = out.len();
+
+    for piece in line.pieces.iter() {
+        if piece.space {
+            out.push(' ');
+        }
+        out.push_str(&piece.text);
+    }
+    if out.len() <= MAX_WIDTH || line.pieces.iter().any(|p| p.text.starts_with("//")) {
+        text.push_str(&out);
+        text.push('\n');
+        return;
+    }
+
+    // wrap after the outermost commas
+    let depth = line
+        .pieces
+        .iter()
+        .filter(|p| p.text == "," && p.depth > 0)
+        .map(|p| p.depth)
+        .min();
+    let depth = match depth {
+        Some(depth) => depth,
+        None => {
+            text.push_str(&out);
+            text.push('\n');
+            return;
+        }
+    };
+    out.truncate(start);
+
+    for piece in line.pieces.iter() {
+        if piece.space && !out.ends_with('\n') {
+            out.push(' ');
+        }
+        if out.ends_with('\n') {
+            out.push_str(&INDENT.repeat(line.indent + 2));
+        }
+        out.push_str(&piece.text);
+        if piece.text == "," && piece.depth == depth {
+            out.push('\n');
+        }
+    }
+    text.push_str(&INDENT.repeat(line.indent));
+    text.push_str(&out);
+    text.push('\n');
+}
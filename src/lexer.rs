@@ -32,37 +32,117 @@ pub enum TokenType {
 #[derive(Debug, Clone)]
 pub struct Token<'src> {
     pub tt: TokenType,
-    // avoid local String is freed before return
+    // borrowed from source, owned for error message
     pub value: Cow<'src, str>,
+    // raw source text, e.g. string with quotes
+    pub lexeme: &'src str,
+    pub span: Span,
     pub line: usize,
+    pub col: usize,
+    // whitespace & comments before the token,
+    // only collected by `Scanner::with_trivia`
+    pub leading: Vec<Trivia<'src>>,
+}
+
+// byte offsets in source, end exclusive
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // span covering both
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+// line & column (from 1) of a byte offset in source
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source.as_bytes()[..offset.min(source.len())];
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+
+    (line, offset - line_start + 1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia<'src> {
+    pub kind: TriviaKind,
+    pub text: &'src str,
 }
 
 #[derive(Debug)]
 pub struct Scanner<'src> {
-    pub source: &'src [u8],
+    pub source: &'src str,
     pub current: usize,
     pub line: usize,
+    // offset of the first char of current line
+    pub line_start: usize,
+    // offset, line & column of the first char of current token
+    start: usize,
+    start_line: usize,
+    start_col: usize,
+    // Eof token has been emitted
+    done: bool,
+    // keep whitespace & comments
+    trivia: bool,
+    pending: Vec<Trivia<'src>>,
 }
 
 impl<'src> Scanner<'src> {
     pub fn new(text: &'src str) -> Scanner<'src> {
         Scanner {
-            source: text.as_bytes(),
+            source: text,
             current: 0,
-            line: 0,
+            line: 1,
+            line_start: 0,
+            start: 0,
+            start_line: 1,
+            start_col: 1,
+            done: false,
+            trivia: false,
+            pending: Vec::new(),
+        }
+    }
+
+    // lossless mode for tooling, source can be rebuilt
+    // from the tokens, see `reconstruct`
+    pub fn with_trivia(text: &'src str) -> Scanner<'src> {
+        Scanner {
+            trivia: true,
+            ..Scanner::new(text)
         }
     }
 
     #[rustfmt::skip]
-    pub fn next(&mut self) -> Token<'static> {
+    pub fn scan_token(&mut self) -> Token<'src> {
         self.skip_white_space();
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_col = self.current - self.line_start + 1;
 
         if self.at_end() {
             return token!(self, "EOF", TokenType::Eof);
         }
         self.current += 1;
 
-        let cur = self.source[self.current - 1];
+        let cur = self.byte(self.current - 1);
         match cur {
             b'(' => token!(self, "(", TokenType::LeftParen),
             b')' => token!(self, ")", TokenType::RightParen),
@@ -86,22 +166,31 @@ impl<'src> Scanner<'src> {
             b'0'..=b'9' => self.number(),
             b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.identifier(),
 
-            _ => token!(self, "UNK", TokenType::Error),
+            _ => self.unexpected_char(),
         }
     }
 
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current + 1;
+    }
+
+    fn byte(&self, offset: usize) -> u8 {
+        self.source.as_bytes()[offset]
+    }
+
     fn peek(&self) -> u8 {
         if self.at_end() {
             return b'\0';
         }
-        self.source[self.current]
+        self.byte(self.current)
     }
 
     fn peek_next(&self) -> u8 {
         if self.current + 1 >= self.source.len() {
             return b'\0';
         }
-        self.source[self.current + 1]
+        self.byte(self.current + 1)
     }
 
     // see if next char matches expect
@@ -118,54 +207,57 @@ impl<'src> Scanner<'src> {
         self.current >= self.source.len()
     }
 
-    // handle comment
+    // handle comment, stop before the newline
     fn skip_comment(&mut self) {
-        loop {
-            let cur = self.peek();
-            match cur {
-                b'\n' | b'\0' => {
-                    self.line += 1;
-                    self.current += 1;
-                    break;
-                }
-                _ => self.current += 1,
-            }
+        while self.peek() != b'\n' && !self.at_end() {
+            self.current += 1;
         }
     }
 
-    // handle space
+    // handle space, keep it as trivia if asked
     fn skip_white_space(&mut self) {
         loop {
-            let cur = self.peek();
-            match cur {
-                b'\r' | b'\t' | b' ' => self.current += 1,
+            let start = self.current;
+            let kind = match self.peek() {
+                b'\r' | b'\t' | b' ' => {
+                    while matches!(self.peek(), b'\r' | b'\t' | b' ') {
+                        self.current += 1;
+                    }
+                    TriviaKind::Whitespace
+                }
                 b'\n' => {
-                    self.line += 1;
+                    self.newline();
                     self.current += 1;
+                    TriviaKind::Newline
                 }
-                b'/' => {
-                    if self.peek_next() != b'/' {
-                        break;
-                    }
+                b'/' if self.peek_next() == b'/' => {
                     self.skip_comment();
+                    TriviaKind::Comment
                 }
                 _ => break,
+            };
+            if self.trivia {
+                self.pending.push(Trivia {
+                    kind,
+                    text: &self.source[start..self.current],
+                });
             }
         }
     }
 
     // handle string
-    fn string(&mut self) -> Token<'static> {
+    fn string(&mut self) -> Token<'src> {
         let start = self.current;
 
         while self.peek() != b'"' && !self.at_end() {
             if self.peek() == b'\n' {
-                self.line += 1;
+                self.newline();
             }
             self.current += 1;
         }
         if self.at_end() {
-            return token!(self, "Unterminated string", TokenType::Error);
+            let msg = format!("Unterminated string starting at line {}", self.start_line);
+            return token!(self, msg, TokenType::Error);
         }
         let end = self.current;
         // enclosing string
@@ -174,13 +266,25 @@ impl<'src> Scanner<'src> {
         token!(self, self._string(start, end), TokenType::Str)
     }
 
-    fn _string(&self, start: usize, end: usize) -> String {
-        // self.source[start..end].iter().collect::<String>()
-        String::from(std::str::from_utf8(&self.source[start..end]).unwrap())
+    // handle unknown char, which may be multi-byte utf-8
+    fn unexpected_char(&mut self) -> Token<'src> {
+        let ch = self.source[self.start..].chars().next().unwrap();
+
+        self.current = self.start + ch.len_utf8();
+        token!(
+            self,
+            format!("Unexpected character '{}'", ch),
+            TokenType::Error
+        )
+    }
+
+    // borrow from source, token boundaries are always ascii
+    fn _string(&self, start: usize, end: usize) -> &'src str {
+        &self.source[start..end]
     }
 
     // handle number
-    fn number(&mut self) -> Token<'static> {
+    fn number(&mut self) -> Token<'src> {
         let start = self.current - 1;
 
         while self._is_digit(self.peek()) {
@@ -198,7 +302,7 @@ impl<'src> Scanner<'src> {
     }
 
     // handle identifier
-    fn identifier(&mut self) -> Token<'static> {
+    fn identifier(&mut self) -> Token<'src> {
         let start = self.current - 1;
 
         while self._is_alpha(self.peek()) || self._is_digit(self.peek()) {
@@ -207,7 +311,7 @@ impl<'src> Scanner<'src> {
         let end = self.current;
 
         let val = self._string(start, end);
-        let typ = self._identifier_type(&val);
+        let typ = self._identifier_type(val);
 
         token!(self, val, typ)
     }
@@ -238,16 +342,46 @@ impl<'src> Scanner<'src> {
 
     // utils
     fn _is_alpha(&self, cur: u8) -> bool {
-        match cur {
-            b'A'..=b'Z' | b'a'..=b'z' | b'_' => true,
-            _ => false,
-        }
+        matches!(cur, b'A'..=b'Z' | b'a'..=b'z' | b'_')
     }
 
     fn _is_digit(&self, cur: u8) -> bool {
-        match cur {
-            b'0'..=b'9' => true,
-            _ => false,
+        cur.is_ascii_digit()
+    }
+}
+
+// yield tokens until Eof (inclusive)
+impl<'src> Iterator for Scanner<'src> {
+    type Item = Token<'src>;
+
+    fn next(&mut self) -> Option<Token<'src>> {
+        if self.done {
+            return None;
+        }
+        let token = self.scan_token();
+        if let TokenType::Eof = token.tt {
+            self.done = true;
         }
+        Some(token)
     }
 }
+
+/// Rebuild the source text from tokens scanned by `Scanner::with_trivia`.
+pub fn reconstruct(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        for trivia in token.leading.iter() {
+            text.push_str(trivia.text);
+        }
+        text.push_str(token.lexeme);
+    }
+    text
+}
+
+/// Scan the whole source into tokens, the last one is always `Eof`.
+///
+/// Lexical errors don't stop scanning, they are returned as `Error`
+/// tokens whose value is the error message.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    Scanner::new(source).collect()
+}
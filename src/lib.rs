@@ -1,15 +1,57 @@
+//! Lox, from Crafting Interpreters, as a bytecode VM.
+//!
+//! Embed it with a [`Vm`], which keeps globals between calls:
+//!
+//! ```
+//! use rlox::{Config, Value, Vm};
+//!
+//! let mut vm = Vm::new(Config::default());
+//! vm.define_fn("add", |a: f64, b: f64| a + b);
+//! vm.set_global("ten", 10.0);
+//!
+//! assert_eq!(vm.eval("add(ten, 2) * 2")?, Value::from(24.0));
+//! assert_eq!(vm.call("add", &[Value::from(1.0), Value::from(2.0)])?, Value::from(3.0));
+//! assert!(vm.eval("add(1)").is_err());
+//! # Ok::<(), rlox::LoxError>(())
+//! ```
+//!
+//! The modules below are the pieces the `rlox` binary is built
+//! from: compiled chunks, the bytecode format, the debugger,
+//! formatter, linter & language server.
+
 #[macro_use]
 mod macros;
 
+pub mod ast;
+pub mod bench;
+pub mod bytecode;
 pub mod chunk;
 mod compiler;
+pub mod convert;
+pub mod dap;
 pub mod debug;
-mod lexer;
-mod run;
+pub mod debugger;
+pub mod diagnostic;
+mod embed;
+pub mod format;
+pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod native;
+pub mod optimize;
+pub mod stepper;
+mod transport;
+pub mod value;
+pub mod verify;
 pub mod vm;
 
+pub use ast::parse;
+pub use embed::{compile, Config, LoxError, Vm};
+pub use lexer::tokenize;
+pub use native::RuntimeError;
+pub use value::Value;
+
 pub const DEBUG_LEXER: bool = false;
-pub const DEBUG_TRACE: bool = true;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,343 @@
+// Static checks over the syntax tree, reported as warnings:
+//
+//   W001  local variable is never read
+//   W002  unreachable code after `return`
+//   W003  local variable shadows a global
+//   W004  expression compared with itself, e.g. `x == x`
+//
+// A warning is suppressed by a comment on the same line:
+//
+//   var unused = 1; // lint: allow(W001)
+//   var unused = 1; // lint: allow
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::*;
+
+const SUPPRESS: &str = "lint: allow";
+
+/// Lint the source, or return its syntax errors.
+pub fn lint(source: &str) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let program = parse(source)?;
+
+    let mut linter = Linter::new(source, &program);
+    for decl in program.decls.iter() {
+        linter.decl(decl);
+    }
+    if let Some(value) = &program.value {
+        linter.expr(value);
+    }
+
+    let allowed = suppressions(source);
+    let mut warnings = linter.warnings;
+    warnings.retain(|warn| match allowed.get(&warn.line) {
+        Some(None) => false,
+        Some(Some(codes)) => !codes.iter().any(|code| Some(code.as_str()) == warn.code),
+        None => true,
+    });
+    warnings.sort_by_key(|warn| warn.span.start);
+    Ok(warnings)
+}
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    span: Span,
+    used: bool,
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    globals: HashSet<&'a str>,
+    // innermost scope last, empty at top level
+    scopes: Vec<Vec<Local>>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(source: &'a str, program: &'a Program) -> Linter<'a> {
+        let globals = program
+            .decls
+            .iter()
+            .filter_map(|decl| match decl {
+                Decl::Class(class) => Some(class.name.name.as_str()),
+                Decl::Fun(fun) => Some(fun.name.name.as_str()),
+                Decl::Var(var) => Some(var.name.name.as_str()),
+                Decl::Stmt(_) => None,
+            })
+            .collect();
+
+        Linter {
+            source,
+            globals,
+            scopes: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, code: &'static str, msg: &str, span: Span) {
+        let (line, col) = position(self.source, span.start);
+        let warn = Diagnostic::warning(code, msg, span, line, col);
+        self.warnings.push(warn);
+    }
+
+    fn text(&self, span: Span) -> &'a str {
+        &self.source[span.start..span.end]
+    }
+
+    /* Scopes */
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+
+        for local in scope.iter().filter(|local| !local.used) {
+            let msg = format!("Local variable '{}' is never used.", local.name);
+            self.warn("W001", &msg, local.span);
+        }
+    }
+
+    // params & functions count as used, they are often
+    // required by signature or called recursively
+    fn declare(&mut self, name: &Ident, used: bool) {
+        if self.scopes.is_empty() {
+            return;
+        }
+        if self.globals.contains(name.name.as_str()) {
+            let msg = format!("Local variable '{}' shadows a global.", name.name);
+            self.warn("W003", &msg, name.span);
+        }
+        self.scopes.last_mut().unwrap().push(Local {
+            name: name.name.clone(),
+            span: name.span,
+            used,
+        });
+    }
+
+    fn resolve(&mut self, name: &Ident) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(local) = scope.iter_mut().rev().find(|l| l.name == name.name) {
+                local.used = true;
+                return;
+            }
+        }
+    }
+
+    /* Declarations */
+
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Class(class) => {
+                self.declare(&class.name, true);
+                if let Some(superclass) = &class.superclass {
+                    self.resolve(superclass);
+                }
+                for method in class.methods.iter() {
+                    self.function(method);
+                }
+            }
+            Decl::Fun(fun) => {
+                self.declare(&fun.name, true);
+                self.function(fun);
+            }
+            Decl::Var(var) => {
+                if let Some(init) = &var.init {
+                    self.expr(init);
+                }
+                self.declare(&var.name, false);
+            }
+            Decl::Stmt(stmt) => self.stmt(stmt),
+        }
+    }
+
+    fn function(&mut self, fun: &Function) {
+        self.begin_scope();
+        for param in fun.params.iter() {
+            self.declare(param, true);
+        }
+        self.decls(&fun.body);
+        self.end_scope();
+    }
+
+    // body of a block or function
+    fn decls(&mut self, decls: &[Decl]) {
+        let mut returned = false;
+
+        for decl in decls.iter() {
+            if returned {
+                self.warn("W002", "Unreachable code after 'return'.", decl.span());
+                // only report the first one
+                returned = false;
+            }
+            self.decl(decl);
+
+            if let Decl::Stmt(Stmt {
+                kind: StmtKind::Return(_),
+                ..
+            }) = decl
+            {
+                returned = true;
+            }
+        }
+    }
+
+    /* Statements */
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.expr(expr),
+            StmtKind::Block(decls) => {
+                self.begin_scope();
+                self.decls(decls);
+                self.end_scope();
+            }
+            StmtKind::If { cond, then, els } => {
+                self.expr(cond);
+                self.stmt(then);
+                if let Some(els) = els {
+                    self.stmt(els);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            StmtKind::For {
+                init,
+                cond,
+                incr,
+                body,
+            } => {
+                self.begin_scope();
+                if let Some(init) = init {
+                    self.decl(init);
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                if let Some(incr) = incr {
+                    self.expr(incr);
+                }
+                self.stmt(body);
+                self.end_scope();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    /* Expressions */
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Variable(name) => self.resolve(name),
+            ExprKind::Assign { value, .. } => self.expr(value),
+            ExprKind::Grouping(expr) | ExprKind::Unary { expr, .. } => self.expr(expr),
+            ExprKind::Binary { op, left, right } => {
+                if is_comparison(*op) && same_place(left, right) {
+                    let msg = format!("Comparing '{}' with itself.", self.text(left.span));
+                    self.warn("W004", &msg, expr.span);
+                }
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Logical { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args.iter() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Get { object, .. } => self.expr(object),
+            ExprKind::Set { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            ExprKind::Nil
+            | ExprKind::Bool(_)
+            | ExprKind::Number(_)
+            | ExprKind::Str(_)
+            | ExprKind::This
+            | ExprKind::Super(_) => {}
+        }
+    }
+}
+
+fn is_comparison(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual
+            | BinaryOp::Less
+            | BinaryOp::LessEqual
+    )
+}
+
+// both sides read the same variable or property
+fn same_place(left: &Expr, right: &Expr) -> bool {
+    match (&left.kind, &right.kind) {
+        (ExprKind::Grouping(left), _) => same_place(left, right),
+        (_, ExprKind::Grouping(right)) => same_place(left, right),
+        (ExprKind::Variable(a), ExprKind::Variable(b)) => a.name == b.name,
+        (ExprKind::This, ExprKind::This) => true,
+        (
+            ExprKind::Get {
+                object: a,
+                name: name_a,
+            },
+            ExprKind::Get {
+                object: b,
+                name: name_b,
+            },
+        ) => name_a.name == name_b.name && same_place(a, b),
+        _ => false,
+    }
+}
+
+// line -> allowed codes, `None` allows all
+fn suppressions(source: &str) -> HashMap<usize, Option<Vec<String>>> {
+    let mut allowed = HashMap::new();
+    let mut line = 1;
+
+    for token in Scanner::with_trivia(source) {
+        for trivia in token.leading.iter() {
+            match trivia.kind {
+                TriviaKind::Newline => line += 1,
+                TriviaKind::Comment => {
+                    if let Some(codes) = suppressed_codes(trivia.text) {
+                        allowed.insert(line, codes);
+                    }
+                }
+                TriviaKind::Whitespace => {}
+            }
+        }
+        // multi-line strings
+        line += token.lexeme.matches('\n').count();
+    }
+    allowed
+}
+
+fn suppressed_codes(comment: &str) -> Option<Option<Vec<String>>> {
+    let rest = comment.trim_start_matches('/').trim_start();
+    let rest = rest.strip_prefix(SUPPRESS)?.trim();
+
+    if rest.is_empty() {
+        return Some(None);
+    }
+    let codes = rest.strip_prefix('(')?.strip_suffix(')')?;
+    let codes = codes.split(',').map(|code| code.trim().to_string());
+    Some(Some(codes.collect()))
+}
@@ -0,0 +1,311 @@
+// Symbols declared in a document and where they are used,
+// resolved with the same scoping rules as the compiler.
+
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Class,
+    Method,
+    Function,
+    Parameter,
+    Variable,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // the name in declaration
+    pub name_span: Span,
+    // the whole declaration
+    pub span: Span,
+    // declaration shown on hover, e.g. `fun add(a, b)`
+    pub detail: String,
+    // symbols declared inside, methods of a class
+    pub children: Vec<usize>,
+    pub top_level: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    pub symbols: Vec<Symbol>,
+    // (name span, symbol), including declarations
+    pub refs: Vec<(Span, usize)>,
+}
+
+impl Index {
+    pub fn new(program: &Program) -> Index {
+        let mut builder = Builder::default();
+
+        // globals can be used before declared, e.g. in functions
+        for decl in program.decls.iter() {
+            if let Some(idx) = builder.declare_decl(decl, true) {
+                let name = builder.index.symbols[idx].name.clone();
+                builder.globals.insert(name, idx);
+            }
+        }
+        for decl in program.decls.iter() {
+            builder.decl(decl);
+        }
+        if let Some(value) = &program.value {
+            builder.expr(value);
+        }
+        builder.index
+    }
+
+    // symbol of the name at offset
+    pub fn find(&self, offset: usize) -> Option<&Symbol> {
+        self.refs
+            .iter()
+            .find(|(span, _)| span.start <= offset && offset <= span.end)
+            .map(|(_, idx)| &self.symbols[*idx])
+    }
+
+    pub fn kind_at(&self, span: Span) -> Option<SymbolKind> {
+        self.refs
+            .iter()
+            .find(|(s, _)| *s == span)
+            .map(|(_, idx)| self.symbols[*idx].kind)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Builder {
+    index: Index,
+    globals: HashMap<String, usize>,
+    methods: HashMap<String, usize>,
+    scopes: Vec<Vec<usize>>,
+    // symbol of top level decls, declared ahead
+    ahead: HashMap<usize, usize>,
+}
+
+impl Builder {
+    fn add(&mut self, name: &Ident, kind: SymbolKind, span: Span, detail: String) -> usize {
+        let idx = self.index.symbols.len();
+        self.index.symbols.push(Symbol {
+            name: name.name.clone(),
+            kind,
+            name_span: name.span,
+            span,
+            detail,
+            children: Vec::new(),
+            top_level: self.scopes.is_empty() && kind != SymbolKind::Method,
+        });
+        self.index.refs.push((name.span, idx));
+        idx
+    }
+
+    // returns symbol of class, function or variable
+    fn declare_decl(&mut self, decl: &Decl, ahead: bool) -> Option<usize> {
+        let idx = match decl {
+            Decl::Class(class) => {
+                let detail = match &class.superclass {
+                    Some(sup) => format!("class {} < {}", class.name.name, sup.name),
+                    None => format!("class {}", class.name.name),
+                };
+                let idx = self.add(&class.name, SymbolKind::Class, class.span, detail);
+
+                for method in class.methods.iter() {
+                    let detail = signature("", method);
+                    let child = self.add(&method.name, SymbolKind::Method, method.span, detail);
+                    self.index.symbols[idx].children.push(child);
+                    self.methods
+                        .entry(method.name.name.clone())
+                        .or_insert(child);
+                }
+                idx
+            }
+            Decl::Fun(fun) => {
+                let detail = signature("fun ", fun);
+                self.add(&fun.name, SymbolKind::Function, fun.span, detail)
+            }
+            Decl::Var(var) => {
+                let detail = format!("var {}", var.name.name);
+                self.add(&var.name, SymbolKind::Variable, var.span, detail)
+            }
+            Decl::Stmt(_) => return None,
+        };
+        if ahead {
+            self.ahead.insert(decl.span().start, idx);
+        }
+        Some(idx)
+    }
+
+    fn declare(&mut self, decl: &Decl) -> Option<usize> {
+        let idx = match self.ahead.get(&decl.span().start) {
+            Some(idx) if self.scopes.is_empty() => Some(*idx),
+            _ => self.declare_decl(decl, false),
+        };
+        if let (Some(idx), Some(scope)) = (idx, self.scopes.last_mut()) {
+            scope.push(idx);
+        }
+        idx
+    }
+
+    fn resolve(&mut self, name: &Ident) {
+        let local = self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .iter()
+                .rev()
+                .find(|idx| self.index.symbols[**idx].name == name.name)
+        });
+        let found = local.or_else(|| self.globals.get(&name.name)).copied();
+
+        if let Some(idx) = found {
+            self.index.refs.push((name.span, idx));
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Class(class) => {
+                let idx = self.declare(decl);
+                if let Some(superclass) = &class.superclass {
+                    self.resolve(superclass);
+                }
+                let methods = idx.map(|idx| self.index.symbols[idx].children.clone());
+                for (i, method) in class.methods.iter().enumerate() {
+                    let parent = methods.as_ref().map(|m| m[i]);
+                    self.function(method, parent);
+                }
+            }
+            Decl::Fun(fun) => {
+                let idx = self.declare(decl);
+                self.function(fun, idx);
+            }
+            Decl::Var(var) => {
+                if let Some(init) = &var.init {
+                    self.expr(init);
+                }
+                self.declare(decl);
+            }
+            Decl::Stmt(stmt) => self.stmt(stmt),
+        }
+    }
+
+    fn function(&mut self, fun: &Function, parent: Option<usize>) {
+        self.scopes.push(Vec::new());
+
+        for param in fun.params.iter() {
+            let detail = format!("(parameter) {}", param.name);
+            let idx = self.add(param, SymbolKind::Parameter, param.span, detail);
+            self.scopes.last_mut().unwrap().push(idx);
+        }
+        for decl in fun.body.iter() {
+            self.decl(decl);
+
+            // nested functions are listed in outline
+            if let (Decl::Fun(_), Some(parent)) = (decl, parent) {
+                let child = *self.scopes.last().unwrap().last().unwrap();
+                self.index.symbols[parent].children.push(child);
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, decls: &[Decl]) {
+        self.scopes.push(Vec::new());
+        for decl in decls.iter() {
+            self.decl(decl);
+        }
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.expr(expr),
+            StmtKind::Block(decls) => self.block(decls),
+            StmtKind::If { cond, then, els } => {
+                self.expr(cond);
+                self.stmt(then);
+                if let Some(els) = els {
+                    self.stmt(els);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            StmtKind::For {
+                init,
+                cond,
+                incr,
+                body,
+            } => {
+                self.scopes.push(Vec::new());
+                if let Some(init) = init {
+                    self.decl(init);
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                if let Some(incr) = incr {
+                    self.expr(incr);
+                }
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Variable(name) => self.resolve(name),
+            ExprKind::Assign { name, value } => {
+                self.resolve(name);
+                self.expr(value);
+            }
+            ExprKind::Grouping(expr) | ExprKind::Unary { expr, .. } => self.expr(expr),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args.iter() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Get { object, name } => {
+                self.expr(object);
+                self.property(name);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expr(object);
+                self.property(name);
+                self.expr(value);
+            }
+            ExprKind::Super(method) => self.property(method),
+            ExprKind::Nil
+            | ExprKind::Bool(_)
+            | ExprKind::Number(_)
+            | ExprKind::Str(_)
+            | ExprKind::This => {}
+        }
+    }
+
+    // no types, a property resolves to any method with its name
+    fn property(&mut self, name: &Ident) {
+        if let Some(idx) = self.methods.get(&name.name) {
+            self.index.refs.push((name.span, *idx));
+        }
+    }
+}
+
+fn signature(prefix: &str, fun: &Function) -> String {
+    let params: Vec<&str> = fun.params.iter().map(|p| p.name.as_str()).collect();
+    format!("{}{}({})", prefix, fun.name.name, params.join(", "))
+}
@@ -0,0 +1,393 @@
+// Language server over stdio, speaking JSON-RPC as in
+//   https://microsoft.github.io/language-server-protocol/
+//
+// Documents are synced in full, each change is parsed, linted &
+// compiled again, which is fast enough for scripts of any sane size.
+//
+// Try it by piping messages:
+//
+//   printf 'Content-Length: 46\r\n\r\n{"jsonrpc":"2.0","id":1,"method":"initialize"}' | rlox lsp
+
+mod index;
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::ast::parse;
+use crate::compiler::compile_diagnostics;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::lexer::*;
+use crate::lint::lint;
+use crate::transport::*;
+
+use index::{Index, SymbolKind};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// semantic token legend, index is the token type
+const TOKEN_TYPES: [&str; 10] = [
+    "keyword",
+    "string",
+    "number",
+    "operator",
+    "variable",
+    "function",
+    "class",
+    "parameter",
+    "method",
+    "comment",
+];
+
+/// Serve requests until `exit` or end of input.
+pub fn serve<R: BufRead, W: Write>(input: R, output: W) -> std::io::Result<()> {
+    let mut server = Server {
+        input,
+        output,
+        documents: HashMap::new(),
+    };
+    server.run()
+}
+
+struct Server<R, W> {
+    input: R,
+    output: W,
+    // uri -> text
+    documents: HashMap<String, String>,
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    fn run(&mut self) -> std::io::Result<()> {
+        while let Some(msg) = self.read()? {
+            let method = msg["method"].as_str().unwrap_or("");
+            let params = &msg["params"];
+
+            let result = match method {
+                "initialize" => Ok(capabilities()),
+                "shutdown" => Ok(Value::Null),
+                "exit" => return Ok(()),
+
+                "textDocument/didOpen" => {
+                    let doc = &params["textDocument"];
+                    self.update(&doc["uri"], &doc["text"])?;
+                    continue;
+                }
+                "textDocument/didChange" => {
+                    // full sync, the last change is the whole text
+                    let changes = params["contentChanges"].as_array();
+                    let text = changes.and_then(|c| c.last()).map(|c| &c["text"]);
+                    self.update(&params["textDocument"]["uri"], text.unwrap_or(&Value::Null))?;
+                    continue;
+                }
+                "textDocument/didClose" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    self.documents.remove(uri);
+                    continue;
+                }
+
+                "textDocument/definition" => self.with_document(params, definition),
+                "textDocument/hover" => self.with_document(params, hover),
+                "textDocument/documentSymbol" => self.with_document(params, document_symbols),
+                "textDocument/semanticTokens/full" => self.with_document(params, semantic_tokens),
+
+                _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'.", method))),
+            };
+
+            // notifications have no id and no response
+            let id = &msg["id"];
+            if id.is_null() {
+                continue;
+            }
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            self.write(&response)?;
+        }
+        Ok(())
+    }
+
+    fn with_document(
+        &self,
+        params: &Value,
+        handler: fn(&str, &Value) -> Value,
+    ) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+
+        match self.documents.get(uri) {
+            Some(text) => Ok(handler(text, params)),
+            None => Err((INVALID_PARAMS, format!("Unknown document '{}'.", uri))),
+        }
+    }
+
+    fn update(&mut self, uri: &Value, text: &Value) -> std::io::Result<()> {
+        let (uri, text) = match (uri.as_str(), text.as_str()) {
+            (Some(uri), Some(text)) => (uri, text),
+            _ => return Ok(()),
+        };
+        // what the VM would refuse once it parses
+        let diagnostics = match lint(text) {
+            Ok(mut warnings) => {
+                warnings.extend(compile_diagnostics(text));
+                warnings
+            }
+            Err(errors) => errors,
+        };
+        let diagnostics: Vec<Value> = diagnostics
+            .iter()
+            .map(|diag| to_diagnostic(text, diag))
+            .collect();
+
+        self.documents.insert(String::from(uri), String::from(text));
+        self.write(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn read(&mut self) -> std::io::Result<Option<Value>> {
+        read_message(&mut self.input)
+    }
+
+    fn write(&mut self, msg: &Value) -> std::io::Result<()> {
+        write_message(&mut self.output, msg)
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            // full document sync
+            "textDocumentSync": 1,
+            "definitionProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "semanticTokensProvider": {
+                "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                "full": true,
+            },
+        },
+        "serverInfo": { "name": "rlox", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+/* Handlers */
+
+fn definition(text: &str, params: &Value) -> Value {
+    let offset = to_offset(text, &params["position"]);
+    let index = match parse(text) {
+        Ok(program) => Index::new(&program),
+        Err(_) => return Value::Null,
+    };
+    match index.find(offset) {
+        Some(symbol) => json!({
+            "uri": params["textDocument"]["uri"],
+            "range": to_range(text, symbol.name_span),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn hover(text: &str, params: &Value) -> Value {
+    let offset = to_offset(text, &params["position"]);
+    let index = match parse(text) {
+        Ok(program) => Index::new(&program),
+        Err(_) => return Value::Null,
+    };
+    match index.find(offset) {
+        Some(symbol) => json!({
+            "contents": { "kind": "markdown", "value": format!("```lox\n{}\n```", symbol.detail) },
+        }),
+        None => Value::Null,
+    }
+}
+
+fn document_symbols(text: &str, _: &Value) -> Value {
+    let index = match parse(text) {
+        Ok(program) => Index::new(&program),
+        Err(_) => return json!([]),
+    };
+
+    let symbols: Vec<Value> = (0..index.symbols.len())
+        .filter(|idx| index.symbols[*idx].top_level)
+        .map(|idx| to_symbol(text, &index, idx))
+        .collect();
+    json!(symbols)
+}
+
+fn semantic_tokens(text: &str, _: &Value) -> Value {
+    let index = parse(text).map(|program| Index::new(&program)).ok();
+    let mut data = Vec::new();
+    let (mut last_line, mut last_col) = (0, 0);
+
+    let mut push = |span: Span, tt: usize| {
+        // tokens can't span lines, split multi-line strings
+        let mut start = span.start;
+        for piece in text[span.start..span.end].split('\n') {
+            let (line, col) = to_position(text, start);
+            let len = piece.trim_end_matches('\r').encode_utf16().count();
+            if len > 0 {
+                let delta_col = if line == last_line {
+                    col - last_col
+                } else {
+                    col
+                };
+                data.extend_from_slice(&[line - last_line, delta_col, len, tt, 0]);
+                last_line = line;
+                last_col = col;
+            }
+            start += piece.len() + 1;
+        }
+    };
+
+    let mut offset = 0;
+    for token in Scanner::with_trivia(text) {
+        for trivia in token.leading.iter() {
+            if trivia.kind == TriviaKind::Comment {
+                push(Span::new(offset, offset + trivia.text.len()), 9);
+            }
+            offset += trivia.text.len();
+        }
+        if let Some(tt) = token_type(&token, index.as_ref()) {
+            push(token.span, tt);
+        }
+        offset = token.span.end;
+    }
+    json!({ "data": data })
+}
+
+// index of `TOKEN_TYPES`
+fn token_type(token: &Token, index: Option<&Index>) -> Option<usize> {
+    let tt = match token.tt {
+        TokenType::Str => 1,
+        TokenType::Number => 2,
+        TokenType::Identifier => {
+            let kind = index.and_then(|index| index.kind_at(token.span));
+            match kind {
+                Some(SymbolKind::Function) => 5,
+                Some(SymbolKind::Class) => 6,
+                Some(SymbolKind::Parameter) => 7,
+                Some(SymbolKind::Method) => 8,
+                _ => 4,
+            }
+        }
+        TokenType::And
+        | TokenType::Class
+        | TokenType::Else
+        | TokenType::False
+        | TokenType::For
+        | TokenType::Fun
+        | TokenType::If
+        | TokenType::Nil
+        | TokenType::Or
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::Super
+        | TokenType::This
+        | TokenType::True
+        | TokenType::Var
+        | TokenType::While => 0,
+        TokenType::Minus
+        | TokenType::Plus
+        | TokenType::Slash
+        | TokenType::Star
+        | TokenType::Bang
+        | TokenType::BangEqual
+        | TokenType::Equal
+        | TokenType::EqualEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Less
+        | TokenType::LessEqual => 3,
+        _ => return None,
+    };
+    Some(tt)
+}
+
+fn to_symbol(text: &str, index: &Index, idx: usize) -> Value {
+    let symbol = &index.symbols[idx];
+    let children: Vec<Value> = symbol
+        .children
+        .iter()
+        .map(|child| to_symbol(text, index, *child))
+        .collect();
+    // SymbolKind in protocol
+    let kind = match symbol.kind {
+        SymbolKind::Class => 5,
+        SymbolKind::Method => 6,
+        SymbolKind::Function => 12,
+        SymbolKind::Parameter | SymbolKind::Variable => 13,
+    };
+    json!({
+        "name": symbol.name,
+        "detail": symbol.detail,
+        "kind": kind,
+        "range": to_range(text, symbol.span),
+        "selectionRange": to_range(text, symbol.name_span),
+        "children": children,
+    })
+}
+
+/* Positions, protocol counts lines from 0 and columns in utf-16 */
+
+fn to_position(text: &str, offset: usize) -> (usize, usize) {
+    let (line, _) = position(text, offset);
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let col = text[line_start..offset].encode_utf16().count();
+
+    (line - 1, col)
+}
+
+fn to_offset(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let col = pos["character"].as_u64().unwrap_or(0) as usize;
+
+    let mut offset = 0;
+    for _ in 0..line {
+        match text[offset..].find('\n') {
+            Some(i) => offset += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for ch in text[offset..].chars() {
+        if units >= col || ch == '\n' {
+            break;
+        }
+        units += ch.len_utf16();
+        offset += ch.len_utf8();
+    }
+    offset
+}
+
+fn to_range(text: &str, span: Span) -> Value {
+    let (start_line, start_col) = to_position(text, span.start);
+    let (end_line, end_col) = to_position(text, span.end);
+
+    json!({
+        "start": { "line": start_line, "character": start_col },
+        "end": { "line": end_line, "character": end_col },
+    })
+}
+
+fn to_diagnostic(text: &str, diag: &Diagnostic) -> Value {
+    // protocol severity
+    let severity = match diag.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    json!({
+        "range": to_range(text, diag.span),
+        "severity": severity,
+        "code": diag.code,
+        "source": "rlox",
+        "message": diag.message,
+    })
+}
@@ -3,7 +3,11 @@ macro_rules! token {
         Token {
             tt: $tt,
             value: Cow::from($val),
-            line: $self.line,
+            lexeme: &$self.source[$self.start..$self.current],
+            span: Span::new($self.start, $self.current),
+            line: $self.start_line,
+            col: $self.start_col,
+            leading: std::mem::take(&mut $self.pending),
         }
     };
 
@@ -11,7 +15,11 @@ macro_rules! token {
         Token {
             tt: $tt,
             value: Cow::from($val),
-            line: $self.line,
+            lexeme: &$self.source[$self.start..$self.current],
+            span: Span::new($self.start, $self.current),
+            line: $self.start_line,
+            col: $self.start_col,
+            leading: std::mem::take(&mut $self.pending),
         }
     };
 }
@@ -4,6 +4,9 @@ pub mod vm;
 #[macro_use]
 pub mod lexer;
 
+#[macro_use]
+pub mod native;
+
 // generic macros
 
 macro_rules! grow_capacity {
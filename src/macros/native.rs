@@ -0,0 +1,24 @@
+// `IntoNative` for closures of `$arity` arguments, each a type
+// parameter & its index
+macro_rules! into_native {
+    ($arity:expr; $($arg:ident $n:tt),*) => {
+        impl<F, R, $($arg),*> IntoNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoLox,
+            $($arg: FromLox,)*
+        {
+            fn arity() -> u8 {
+                $arity
+            }
+
+            #[allow(unused_variables)]
+            fn into_native(self) -> NativeBox {
+                Rc::new(move |_: &mut VirtualMachine, args: &[Value]| {
+                    let result = self($(arg::<$arg>(args, $n)?),*);
+                    Ok(result.into_lox())
+                })
+            }
+        }
+    };
+}
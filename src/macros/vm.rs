@@ -1,16 +1,61 @@
+// the top of stack is cached in `$top`, values below it live in
+// `$self.stack` up to `$sp`, slots are masked (see vm.rs)
+
+// cache a new top, the old one goes to the stack
+macro_rules! push {
+    ($self:ident, $bytes:ident, $start:ident, $sp:ident, $top:ident, $val:expr) => {
+        if $sp >= $self.limits.stack {
+            return $self.runtime_error($bytes, $start, "Stack overflow.");
+        }
+        $self.stack[$sp & $self.mask] = $top;
+        $sp += 1;
+        $top = $val;
+    };
+}
+
+// number operands or a runtime error at the op
 macro_rules! bin_op {
-    ($self:ident, $op:tt) => {
-        let b = $self.stack.pop().unwrap();
-        let a = $self.stack.pop().unwrap();
-        $self.stack.push(a $op b);
-        $self.ip += 1;
+    ($self:ident, $bytes:ident, $start:ident, $sp:ident, $top:ident, $op:tt) => {
+        $sp = $sp.wrapping_sub(1);
+        let a = $self.stack[$sp & $self.mask];
+        match (a.as_number(), $top.as_number()) {
+            (Some(a), Some(b)) => $top = Value::from(a $op b),
+            _ => return $self.runtime_error($bytes, $start, "Operands must be numbers."),
+        }
     }
 }
 
 macro_rules! unary_op {
-	($self:ident, $op:tt) => {
-		let val = $op $self.stack.pop().unwrap();
-		$self.stack.push(val);
-		$self.ip += 1;
-	}
+    ($self:ident, $bytes:ident, $start:ident, $top:ident, $op:tt) => {
+        match $top.as_number() {
+            Some(val) => $top = Value::from($op val),
+            None => return $self.runtime_error($bytes, $start, "Operand must be a number."),
+        }
+    }
+}
+
+// operand b from the constant pool
+macro_rules! bin_op_const {
+    ($self:ident, $bytes:ident, $start:ident, $top:ident, $idx:expr, $op:tt) => {
+        let b = $bytes.constants[$idx];
+        match ($top.as_number(), b.as_number()) {
+            (Some(a), Some(b)) => $top = Value::from(a $op b),
+            _ => return $self.runtime_error($bytes, $start, "Operands must be numbers."),
+        }
+    }
+}
+
+// push the global named `$idx` in the name table
+macro_rules! get_global {
+    ($self:ident, $bytes:ident, $start:ident, $sp:ident, $top:ident, $idx:expr) => {
+        let name = &$bytes.names[$idx];
+        let val = match $self.globals.get(name) {
+            Some(val) => *val,
+            None => {
+                let msg = format!("Undefined variable '{}'.", name);
+                return $self.runtime_error($bytes, $start, &msg);
+            }
+        };
+        push!($self, $bytes, $start, $sp, $top, val);
+    };
 }
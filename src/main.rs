@@ -1,30 +1,35 @@
-#![allow(dead_code)]
+mod cli;
 
-#[macro_use]
-mod macros;
-
-mod chunk;
-mod compiler;
-mod debug;
-mod lexer;
-mod run;
-mod vm;
-
-use run::{repl, run_file};
+use cli::{
+    bench_files, compile_file, debug_file, fmt_files, lint_files, repl, run_file, serve_dap,
+    serve_lsp, take_opt_level,
+};
 use std::env;
 
-pub const DEBUG_LEXER: bool = false;
-pub const DEBUG_TRACE: bool = true;
-
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let level = take_opt_level(&mut args);
     let n_args = args.len();
 
-    match n_args {
-        1 => repl(),
-        2 => run_file(&args[1]),
+    match args.get(1).map(String::as_str) {
+        None => repl(),
+        Some("bench") => bench_files(&args[2..], level),
+        Some("compile") => compile_file(&args[2..], level),
+        Some("fmt") => fmt_files(&args[2..]),
+        Some("lint") => lint_files(&args[2..]),
+        Some("lsp") => serve_lsp(),
+        Some("dap") => serve_dap(),
+        Some("debug") if n_args == 3 => debug_file(&args[2]),
+        Some(_) if n_args == 2 => run_file(&args[1], level),
         _ => {
-            println!("Usage: ./rlox [script.lox]");
+            println!("Usage: ./rlox [-O<level>] [script.lox]");
+            println!("       ./rlox compile [-O<level>] in.lox [-o out.loxc]");
+            println!("       ./rlox bench [-O<level>] [--json] [files...]");
+            println!("       ./rlox fmt [--check] files...");
+            println!("       ./rlox lint files...");
+            println!("       ./rlox lsp");
+            println!("       ./rlox dap");
+            println!("       ./rlox debug script.lox");
         }
     }
 }
@@ -0,0 +1,97 @@
+// Functions the host provides to scripts, defined as globals with
+// `VirtualMachine::define_native` & called like Lox functions.
+//
+// A native value is a handle into its VM's table, so values stay
+// `Copy` and fit in a NaN box.
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::chunk::Value;
+use crate::convert::*;
+use crate::vm::VirtualMachine;
+
+pub type NativeFn = fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>;
+
+// any native as the VM keeps it
+pub type NativeBox = Rc<dyn Fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>>;
+
+#[derive(Clone)]
+pub struct Native {
+    pub name: String,
+    pub arity: u8,
+    pub function: NativeBox,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}/{}>", self.name, self.arity)
+    }
+}
+
+/// A Lox runtime error, raised by the VM or a native.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    /// Innermost frame first, e.g. `[line 3] in script`, filled
+    /// in by the VM.
+    pub trace: Vec<String>,
+}
+
+impl RuntimeError {
+    pub fn new(message: &str) -> RuntimeError {
+        RuntimeError {
+            message: String::from(message),
+            trace: Vec::new(),
+        }
+    }
+}
+
+// message, then a line per frame
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in self.trace.iter() {
+            write!(f, "\n{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/* Typed Natives */
+
+// a Rust closure taking `Args`, each converted with `FromLox`
+pub trait IntoNative<Args> {
+    fn arity() -> u8;
+    fn into_native(self) -> NativeBox;
+}
+
+// argument `n` (from 0) as `T`, the VM already checked arity
+pub fn arg<T: FromLox>(args: &[Value], n: usize) -> Result<T, RuntimeError> {
+    T::from_lox(args[n]).ok_or_else(|| {
+        let msg = format!("Argument {} must be {}.", n + 1, T::expected());
+        RuntimeError::new(&msg)
+    })
+}
+
+into_native!(0;);
+into_native!(1; A 0);
+into_native!(2; A 0, B 1);
+into_native!(3; A 0, B 1, C 2);
+into_native!(4; A 0, B 1, C 2, D 3);
+into_native!(5; A 0, B 1, C 2, D 3, E 4);
+into_native!(6; A 0, B 1, C 2, D 3, E 4, G 5);
+
+/* Builtins */
+
+// wall clock seconds, differences time a script
+pub fn clock(_: &mut VirtualMachine, _: &[Value]) -> Result<Value, RuntimeError> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| RuntimeError::new("System clock is before 1970."))?;
+    Ok(Value::from(now.as_secs_f64()))
+}
@@ -0,0 +1,118 @@
+// Optimizer over finished chunks, by `-O` level:
+//
+//   0  nothing
+//   1  remove unreachable code & literals popped unused
+//   2  also fuse common sequences into superinstructions
+//
+// Code is decoded into instructions with their source position,
+// rewritten, then encoded into a new chunk with the same constants.
+// There are no jumps yet, so code is unreachable only after a
+// `Return`; threading jumps to jumps goes here once they exist,
+// as do fusions of locals & conditional jumps.
+
+use crate::chunk::*;
+
+pub const MAX_LEVEL: u8 = 2;
+
+// instruction & its (line, col)
+type Located = (Instruction, usize, usize);
+
+pub fn optimize(bytes: &Chunk, level: u8) -> Chunk {
+    if level == 0 {
+        return bytes.clone();
+    }
+    let mut code = decode(bytes);
+
+    remove_dead_code(&mut code);
+    remove_unused(&mut code);
+    if level >= 2 {
+        code = fuse(&code);
+    }
+
+    let mut out = bytes.clone();
+    out.truncate(0);
+    for (ins, line, col) in code.iter() {
+        out.write_instruction(*ins, *line, *col);
+    }
+    out
+}
+
+fn decode(bytes: &Chunk) -> Vec<Located> {
+    let mut code = Vec::new();
+    let mut offset = 0;
+    while let Some(ins) = bytes.decode(offset) {
+        let (line, col) = bytes.get_position(offset);
+        code.push((ins, line, col));
+        offset += ins.size();
+    }
+    code
+}
+
+// nothing runs after the first return
+fn remove_dead_code(code: &mut Vec<Located>) {
+    let ret = code
+        .iter()
+        .position(|(ins, _, _)| *ins == Instruction::Return);
+    if let Some(ret) = ret {
+        code.truncate(ret + 1);
+    }
+}
+
+// a literal pushed & popped, e.g. the statement `1;`, does
+// nothing, unlike a global which may be undefined
+fn remove_unused(code: &mut Vec<Located>) {
+    let mut out: Vec<Located> = Vec::with_capacity(code.len());
+    for located in code.drain(..) {
+        let literal = matches!(
+            out.last(),
+            Some((
+                Instruction::Constant(_)
+                    | Instruction::ConstantLong(_)
+                    | Instruction::Nil
+                    | Instruction::True
+                    | Instruction::False,
+                _,
+                _
+            ))
+        );
+        if located.0 == Instruction::Pop && literal {
+            out.pop();
+        } else {
+            out.push(located);
+        }
+    }
+    *code = out;
+}
+
+// `Constant x; Add` -> `AddConstant x`, likewise for - * /
+// at the position of the arithmetic op, where it could fail
+fn fuse(code: &[Located]) -> Vec<Located> {
+    let mut out = Vec::with_capacity(code.len());
+    let mut i = 0;
+    while i < code.len() {
+        let fused = match (code[i].0, code.get(i + 1)) {
+            (Instruction::Constant(idx), Some((next, line, col))) => {
+                let ins = match next {
+                    Instruction::Add => Some(Instruction::AddConstant(idx)),
+                    Instruction::Subtract => Some(Instruction::SubtractConstant(idx)),
+                    Instruction::Multiply => Some(Instruction::MultiplyConstant(idx)),
+                    Instruction::Divide => Some(Instruction::DivideConstant(idx)),
+                    _ => None,
+                };
+                ins.map(|ins| (ins, *line, *col))
+            }
+            _ => None,
+        };
+        match fused {
+            Some(located) => {
+                out.push(located);
+                i += 2;
+            }
+            None => {
+                out.push(code[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
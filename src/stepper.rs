@@ -0,0 +1,100 @@
+// Breakpoints & stepping shared by the debuggers,
+// lines of ops come from the chunk line table.
+
+use std::collections::BTreeSet;
+
+use crate::chunk::*;
+use crate::vm::VirtualMachine;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    // stop before the first op
+    Entry,
+    // run to the next breakpoint
+    Continue,
+    // next line in the same or outer frame
+    Over,
+    // next line in any frame
+    In,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+#[derive(Debug)]
+pub struct Stepper {
+    pub breakpoints: BTreeSet<usize>,
+    mode: StepMode,
+    // where the step started
+    line: usize,
+    depth: usize,
+    // line of previous op
+    last_line: Option<usize>,
+}
+
+impl Stepper {
+    pub fn new(stop_on_entry: bool) -> Stepper {
+        Stepper {
+            breakpoints: BTreeSet::new(),
+            mode: if stop_on_entry {
+                StepMode::Entry
+            } else {
+                StepMode::Continue
+            },
+            line: 0,
+            depth: 0,
+            last_line: None,
+        }
+    }
+
+    // replace breakpoints, each moves to the first line with code
+    // at or after it, None if there's no such line
+    pub fn set_breakpoints(&mut self, bytes: &Chunk, lines: &[usize]) -> Vec<Option<usize>> {
+        let code_lines: BTreeSet<usize> = bytes.line_runs().iter().map(|run| run.line).collect();
+
+        self.breakpoints.clear();
+        lines
+            .iter()
+            .map(|line| {
+                let actual = code_lines.range(line..).next().copied();
+                if let Some(actual) = actual {
+                    self.breakpoints.insert(actual);
+                }
+                actual
+            })
+            .collect()
+    }
+
+    pub fn resume(&mut self, mode: StepMode, vm: &VirtualMachine, bytes: &Chunk) {
+        self.mode = mode;
+        self.line = bytes.get_line(vm.ip());
+        self.depth = vm.frame_count();
+    }
+
+    // called before each op
+    pub fn should_stop(&mut self, vm: &VirtualMachine, bytes: &Chunk) -> Option<StopReason> {
+        let line = bytes.get_line(vm.ip());
+        let new_line = self.last_line != Some(line);
+        self.last_line = Some(line);
+
+        let depth = vm.frame_count();
+        let stepped = match self.mode {
+            StepMode::Entry => return Some(StopReason::Entry),
+            StepMode::Continue => false,
+            StepMode::Over => line != self.line && depth <= self.depth,
+            StepMode::In => line != self.line || depth != self.depth,
+        };
+
+        if new_line && self.breakpoints.contains(&line) {
+            Some(StopReason::Breakpoint)
+        } else if stepped && new_line {
+            Some(StopReason::Step)
+        } else {
+            None
+        }
+    }
+}
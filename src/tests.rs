@@ -1,20 +1,130 @@
 use crate::chunk::*;
-use crate::debug::Disassembler;
+use crate::debug::{Disassembler, PoolStats};
+use crate::lexer::*;
 use crate::vm::*;
 
 #[test]
 fn test_write_constant() {
     let mut chk = Chunk::new();
-    let constant = chk.add_constant(1.0);
-    chk.write(Op::Constant, 0);
-    chk.write(constant, 0);
+    let constant = chk.write_constant(1.0, 0);
 
+    assert_eq!(constant, Some(0));
     assert_eq!(chk.count, 2);
-    assert_eq!(chk.code[0], Op::Constant);
+    assert_eq!(chk.code[0], Op::Constant as u8);
 
     let (idx, val) = chk.get_constant(0);
     assert_eq!(idx, 0);
-    assert_eq!(val, 1.0);
+    assert_eq!(val, Value::from(1.0));
+}
+
+#[test]
+fn test_write_constant_long() {
+    let mut chk = Chunk::new();
+    for i in 0..300 {
+        chk.write_constant(i as f64, 0);
+    }
+
+    // 256 short, then long
+    assert_eq!(chk.count, 256 * 2 + 44 * 4);
+    assert_eq!(chk.code[512], Op::ConstantLong as u8);
+    assert_eq!(chk.get_constant(512), (256, Value::from(256.0)));
+    assert_eq!(chk.get_constant(512 + 43 * 4), (299, Value::from(299.0)));
+}
+
+#[test]
+fn test_constant_dedup() {
+    let mut chk = Chunk::new();
+    let values = [1.0, 2.0, 1.0, 0.0, -0.0, f64::NAN, f64::NAN, 2.0];
+    let idx: Vec<Option<usize>> = values.iter().map(|v| chk.add_constant(*v)).collect();
+
+    let expected = [0, 1, 0, 2, 3, 4, 4, 1];
+    assert_eq!(idx, expected.iter().map(|i| Some(*i)).collect::<Vec<_>>());
+
+    for v in values.iter() {
+        chk.write_constant(*v, 0);
+    }
+    let stats = PoolStats::new(&chk);
+    assert_eq!(stats.to_string(), "5 constants, 8 loads (0 long)");
+}
+
+#[test]
+fn test_line_table() {
+    let mut chk = Chunk::new();
+    chk.write_at(Op::Nil, 1, 1);
+    chk.write_at(Op::Nil, 1, 1);
+    chk.write_at(Op::Nil, 1, 5);
+    chk.write_at(Op::Nil, 3, 2);
+    chk.write_at(Op::Nil, 1, 1);
+
+    assert_eq!(chk.line_runs().len(), 4);
+    let positions: Vec<(usize, usize)> = (0..6).map(|i| chk.get_position(i)).collect();
+    assert_eq!(
+        positions,
+        vec![(1, 1), (1, 1), (1, 5), (3, 2), (1, 1), (0, 0)]
+    );
+    assert_eq!(chk.get_line(3), 3);
+    assert_eq!(chk.line_offsets(1), vec![0, 2, 4]);
+    assert!(chk.line_offsets(2).is_empty());
+}
+
+#[test]
+fn test_constant_folding() {
+    use crate::compiler::compile_chunk;
+
+    let compile = |source: &str| compile_chunk(source, true).unwrap();
+    let chk = compile("-(1.2 + 3.4) /\n 5.6");
+
+    assert_eq!(chk.count, 3);
+    assert_eq!(chk.get_constant(0).1, Value::from(-(1.2 + 3.4) / 5.6));
+    // position of the last operand, like the unfolded divide
+    assert_eq!(chk.get_position(0), (2, 2));
+
+    // IEEE results are kept, not reduced
+    let chk = compile("-0 * 1");
+    assert_eq!(chk.get_constant(0).1.to_bits(), (-0.0f64).to_bits());
+    let chk = compile("1 / 0 - 1 / 0");
+    assert!(chk.get_constant(0).1.as_number().unwrap().is_nan());
+
+    let chk = compile_chunk("1 + 2", false).unwrap();
+    assert_eq!(chk.count, 6);
+
+    // partial results don't stay in the pool
+    let chk = compile("clock + (1 + 1) * 3 + (1 + 1)");
+    assert_eq!(chk.constants, vec![Value::from(6.0), Value::from(2.0)]);
+
+    // comparisons & `!` fold to what the VM computes
+    let run = |chk: &Chunk| VirtualMachine::new().execute(chk);
+    let sources = [
+        "1 < 2",
+        "2 <= 1",
+        "-1 > -2",
+        "!nil",
+        "!0",
+        "1 == 1 != false",
+        "nil == false",
+        "true == !false",
+        "0 / 0 == 0 / 0",
+        "0 / 0 != 0 / 0",
+        "0 / 0 >= 1",
+        "!(0 / 0 < 1)",
+    ];
+    for source in sources.iter() {
+        let folded = compile(source);
+        // a boolean op & return
+        assert_eq!(folded.count, 2, "{}", source);
+        let unfolded = compile_chunk(source, false).unwrap();
+        assert_eq!(run(&folded), run(&unfolded), "{}", source);
+    }
+    // NaN compares false, so `>=` isn't `!(<)`
+    assert_eq!(run(&compile("0 / 0 >= 1")), Ok(Value::from(false)));
+    assert_eq!(run(&compile("!(0 / 0 < 1)")), Ok(Value::from(true)));
+    assert_eq!(run(&compile("0 / 0 != 0 / 0")), Ok(Value::from(true)));
+
+    // left to fail at runtime
+    let err = run(&compile("1 < nil")).unwrap_err();
+    assert_eq!(err.message, "Operands must be numbers.");
+    let err = run(&compile("-true")).unwrap_err();
+    assert_eq!(err.message, "Operand must be a number.");
 }
 
 #[test]
@@ -26,22 +136,20 @@ fn test_debugger() {
     debugger.disassemble(&chk, "unit test");
 
     assert_eq!(chk.count, 1);
-    assert_eq!(chk.code[0], Op::Nil);
+    assert_eq!(chk.code[0], Op::Nil as u8);
 }
 
 #[test]
 fn test_vm() {
     let mut chk = Chunk::new();
-    let constant = chk.add_constant(1.0);
-    chk.write(Op::Constant, 0);
-    chk.write(constant, 0);
+    chk.write_constant(1.0, 0);
     chk.write(Op::Return, 1);
 
     let vm = VirtualMachine::new();
     let status = vm.interpret(&chk);
 
     assert_eq!(chk.count, 3);
-    assert_eq!(chk.code[2], Op::Return);
+    assert_eq!(chk.code[2], Op::Return as u8);
     assert_eq!(status, InterpretResult::OK);
 }
 
@@ -54,3 +162,289 @@ fn test_clear_chunk() {
     assert_eq!(chk.code.len(), 0);
     assert_eq!(chk.count, 0);
 }
+
+#[test]
+fn test_lexer_errors() {
+    let mut scanner = Scanner::new("1 +\n  @");
+    scanner.scan_token();
+    scanner.scan_token();
+    let token = scanner.scan_token();
+
+    assert_eq!(token.tt, TokenType::Error);
+    assert_eq!(token.value, "Unexpected character '@'");
+    assert_eq!((token.line, token.col), (2, 3));
+
+    let mut scanner = Scanner::new("\n\n\"abc\n");
+    let token = scanner.scan_token();
+
+    assert_eq!(token.tt, TokenType::Error);
+    assert_eq!(token.value, "Unterminated string starting at line 3");
+    assert_eq!((token.line, token.col), (3, 1));
+}
+
+#[test]
+fn test_optimize() {
+    use crate::compiler::compile_with;
+    use crate::optimize::*;
+    use crate::verify::verify;
+
+    let sources = [
+        "1 + 2 * 3",
+        "-(1.2 + 3.4) / 5.6",
+        "1 - 2 - 3",
+        "-0 * 8",
+        "1 / 0",
+        "print 1 + 2;\n3 - 4;",
+        "1;\nnil;\n2 < 3",
+    ];
+    for source in sources.iter() {
+        let mut chk = Chunk::new();
+        assert!(compile_with(source, &mut chk, false));
+
+        let mut expected = Capture::default();
+        VirtualMachine::new().debug(&chk, &mut expected);
+
+        for level in 1..=MAX_LEVEL {
+            let optimized = optimize(&chk, level);
+            assert_eq!(verify(&optimized), Ok(()));
+
+            let mut output = Capture::default();
+            VirtualMachine::new().debug(&optimized, &mut output);
+            assert_eq!(output.output, expected.output, "{} at -O{}", source, level);
+        }
+    }
+
+    // statements with no effect, as compiled
+    let mut chk = Chunk::new();
+    assert!(compile_with(
+        "1 + 2;\nnil;\n!true;\nclock;\n-3",
+        &mut chk,
+        true
+    ));
+    let listing = Disassembler::new().listing(&optimize(&chk, 1));
+    assert_eq!(
+        listing,
+        vec![
+            "0000     4   OP_GET_GLOBAL 0000 clock",
+            "0002     |   OP_POP",
+            "0003     5   OP_CONST  0001 -3",
+            "0005     |   OP_RETURN",
+        ]
+    );
+
+    let mut chk = Chunk::new();
+    assert!(compile_with("1 +\n2 * 3", &mut chk, false));
+    chk.write(Op::Nil, 3);
+
+    let optimized = optimize(&chk, 2);
+    let listing = Disassembler::new().listing(&optimized);
+    assert_eq!(
+        listing,
+        vec![
+            "0000     1   OP_CONST  0000 1",
+            "0002     2   OP_CONST  0001 2",
+            "0004     |   OP_MUL_C  0002 3",
+            "0006     |   OP_ADD",
+            "0007     |   OP_RETURN",
+        ]
+    );
+}
+
+#[test]
+fn test_vm_stack() {
+    // stack before each op, the cached top included
+    struct Stacks(Vec<Vec<Value>>);
+
+    impl DebugHook for Stacks {
+        fn before_op(&mut self, vm: &VirtualMachine, _: &Chunk) -> Resume {
+            self.0.push(vm.stack().to_vec());
+            Resume::Continue
+        }
+
+        fn print(&mut self, _: &str) {}
+    }
+
+    let mut chk = Chunk::new();
+    chk.write_constant(1.0, 0);
+    chk.write_constant(2.0, 0);
+    chk.write(Op::Nil, 0);
+    chk.write(Op::Return, 0);
+
+    let mut stacks = Stacks(Vec::new());
+    VirtualMachine::new().debug(&chk, &mut stacks);
+
+    let (one, two) = (Value::from(1.0), Value::from(2.0));
+    assert_eq!(
+        stacks.0,
+        vec![
+            vec![],
+            vec![one],
+            vec![one, two],
+            vec![one, two, Value::nil()]
+        ]
+    );
+}
+
+#[test]
+fn test_stack_overflow() {
+    let limits = Limits {
+        stack: 3,
+        ..Limits::default()
+    };
+    let mut chk = Chunk::new();
+    for _ in 0..3 {
+        chk.write(Op::Nil, 1);
+    }
+    chk.write(Op::Return, 1);
+
+    let status = VirtualMachine::with_limits(limits).interpret(&chk);
+    assert_eq!(status, InterpretResult::OK);
+
+    chk.truncate(3);
+    chk.write(Op::Nil, 2);
+    chk.write(Op::Return, 2);
+
+    let status = VirtualMachine::with_limits(limits).interpret(&chk);
+    assert_eq!(status, InterpretResult::RuntimeError);
+
+    // no room for the script's frame
+    let limits = Limits {
+        frames: 0,
+        ..Limits::default()
+    };
+    let err = VirtualMachine::with_limits(limits).execute(&chk);
+    assert_eq!(err.unwrap_err().message, "Stack overflow.");
+
+    // clamped, not allocated
+    let limits = Limits {
+        stack: usize::MAX,
+        ..Limits::default()
+    };
+    let vm = VirtualMachine::with_limits(limits);
+    assert_eq!(vm.limits().stack, STACK_CAP);
+}
+
+#[test]
+fn test_native() {
+    use crate::bytecode::{deserialize, serialize};
+    use crate::compiler::compile_chunk;
+    use crate::native::RuntimeError;
+
+    fn max(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+        match (args[0].as_number(), args[1].as_number()) {
+            (Some(a), Some(b)) => Ok(Value::from(a.max(b))),
+            _ => Err(RuntimeError::new("Arguments must be numbers.")),
+        }
+    }
+
+    let run = |source: &str| {
+        let chk = compile_chunk(source, true).unwrap();
+        // names survive a round trip
+        let chk = deserialize(&serialize(&chk)).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.define_native("max", 2, max);
+        vm.execute(&chk).map(|val| val.to_string())
+    };
+    let error = |source: &str| run(source).unwrap_err().to_string();
+
+    assert_eq!(run("max(1, -2) * 3"), Ok(String::from("3")));
+    assert_eq!(run("max"), Ok(String::from("<native fn>")));
+    assert_eq!(run("max(max(1, 5), 4)"), Ok(String::from("5")));
+    assert_eq!(
+        error("max(clock, 1)"),
+        "Arguments must be numbers.\n[line 1] in script"
+    );
+    assert_eq!(
+        error("1;\nmax(1)"),
+        "Expected 2 arguments but got 1.\n[line 2] in script"
+    );
+    assert_eq!(
+        error("min(1, 2)"),
+        "Undefined variable 'min'.\n[line 1] in script"
+    );
+
+    // redefining replaces the native, it doesn't add one
+    fn two(_: &mut VirtualMachine, _: &[Value]) -> Result<Value, RuntimeError> {
+        Ok(Value::from(2.0))
+    }
+
+    let mut vm = VirtualMachine::new();
+    vm.define_native("max", 0, two);
+    let handle = vm.global("max").unwrap();
+    vm.define_native("max", 2, max);
+    vm.define_native("two", 0, two);
+    assert_eq!(vm.global("max"), Some(handle));
+    let chk = compile_chunk("max(two(), 1)", true).unwrap();
+    assert_eq!(vm.execute(&chk), Ok(Value::from(2.0)));
+}
+
+#[test]
+fn test_define_fn() {
+    use crate::compiler::compile_chunk;
+
+    let run = |source: &str| {
+        let chk = compile_chunk(source, true).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.define_fn("add", |a: f64, b: f64| a + b);
+        vm.define_fn("pi", || std::f64::consts::PI);
+        vm.define_fn("ignore", |_: Option<f64>| ());
+        vm.define_fn("either", |a: Option<f64>, b: f64| a.unwrap_or(b));
+
+        let mut output = Capture::default();
+        let status = vm.interpret_with(&chk, &mut output);
+        (status, output.output)
+    };
+
+    assert_eq!(run("add(1, 2) * 2").1, "6");
+    assert_eq!(run("pi()").1, std::f64::consts::PI.to_string());
+    assert_eq!(run("print ignore(1);").1, "nil");
+    assert_eq!(run("either(ignore(1), 3)").1, "3");
+    assert_eq!(run("add(1)").0, InterpretResult::RuntimeError);
+    assert_eq!(run("add(1, pi)").0, InterpretResult::RuntimeError);
+    assert_eq!(run("either(add, 3)").0, InterpretResult::RuntimeError);
+
+    // redefining replaces the native, it doesn't add one
+    let mut vm = VirtualMachine::new();
+    vm.define_fn("two", || 2.0);
+    let handle = vm.global("two").unwrap();
+    vm.define_fn("two", || 2.5);
+    vm.define_fn("three", || 3.0);
+    assert_eq!(vm.global("two"), Some(handle));
+    let chk = compile_chunk("two() + three()", true).unwrap();
+    assert_eq!(vm.execute(&chk), Ok(Value::from(5.5)));
+}
+
+#[test]
+fn test_nested_execute() {
+    use crate::compiler::compile_chunk;
+    use crate::native::RuntimeError;
+
+    fn inner(vm: &mut VirtualMachine, _: &[Value]) -> Result<Value, RuntimeError> {
+        vm.execute(&compile_chunk("x + x", true).unwrap())
+    }
+
+    fn again(vm: &mut VirtualMachine, _: &[Value]) -> Result<Value, RuntimeError> {
+        vm.execute(&compile_chunk("1 + again()", true).unwrap())
+    }
+
+    let mut vm = VirtualMachine::new();
+    vm.set_global("x", Value::from(1.0));
+    vm.define_native("inner", 0, inner);
+    vm.define_native("again", 0, again);
+
+    // the nested run doesn't touch the 5 below it
+    let chk = compile_chunk("5 + inner()", true).unwrap();
+    assert_eq!(vm.execute(&chk), Ok(Value::from(7.0)));
+    assert!(vm.stack().is_empty());
+
+    // each nested run is a frame
+    let chk = compile_chunk("again()", true).unwrap();
+    assert_eq!(vm.execute(&chk).unwrap_err().message, "Stack overflow.");
+    assert_eq!(vm.frame_count(), 1);
+    assert_eq!(
+        vm.execute(&compile_chunk("x", true).unwrap()),
+        Ok(Value::from(1.0))
+    );
+}
@@ -0,0 +1,43 @@
+// Messages framed with a `Content-Length` header, the base
+// protocol of both language server & debug adapter.
+
+use std::io::{BufRead, Error, ErrorKind, Write};
+
+use serde_json::Value;
+
+// largest body read, the length comes from the peer
+pub const MAX_LENGTH: usize = 64 << 20;
+
+// None at end of input, malformed body reads as null
+pub fn read_message<R: BufRead>(input: &mut R) -> std::io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(len) = header.strip_prefix("Content-Length:") {
+            length = len.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_LENGTH {
+        let msg = format!("Content-Length {} is over {}.", length, MAX_LENGTH);
+        return Err(Error::new(ErrorKind::InvalidData, msg));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+pub fn write_message<W: Write>(output: &mut W, msg: &Value) -> std::io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
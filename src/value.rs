@@ -0,0 +1,204 @@
+// Values of the VM, either a tagged enum (default) or, with the
+// `nan-boxing` feature, packed into the unused bits of a quiet NaN
+// as in clox:
+//
+//   number  any f64 that isn't our quiet NaN pattern
+//   nil     QNAN | 1
+//   false   QNAN | 2
+//   true    QNAN | 3
+//   object  SIGN | QNAN | handle, a native's index in its VM
+//
+// Both expose the same methods, VM code must not look inside.
+
+use std::fmt;
+
+#[cfg(not(feature = "nan-boxing"))]
+pub use tagged::Value;
+
+#[cfg(feature = "nan-boxing")]
+pub use boxed::Value;
+
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+const OBJ: u64 = 0x8000_0000_0000_0000 | QNAN;
+
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Value {
+        Nil,
+        Bool(bool),
+        Number(f64),
+        Native(u32),
+    }
+
+    impl Value {
+        pub fn nil() -> Value {
+            Value::Nil
+        }
+
+        pub fn is_nil(&self) -> bool {
+            matches!(self, Value::Nil)
+        }
+
+        pub fn as_bool(&self) -> Option<bool> {
+            match self {
+                Value::Bool(b) => Some(*b),
+                _ => None,
+            }
+        }
+
+        pub fn as_number(&self) -> Option<f64> {
+            match self {
+                Value::Number(n) => Some(*n),
+                _ => None,
+            }
+        }
+
+        pub fn native(handle: u32) -> Value {
+            Value::Native(handle)
+        }
+
+        pub fn as_native(&self) -> Option<u32> {
+            match self {
+                Value::Native(handle) => Some(*handle),
+                _ => None,
+            }
+        }
+
+        // identity, the NaN-boxed word for any representation
+        pub fn to_bits(self) -> u64 {
+            match self {
+                Value::Nil => QNAN | TAG_NIL,
+                Value::Bool(false) => QNAN | TAG_FALSE,
+                Value::Bool(true) => QNAN | TAG_TRUE,
+                Value::Number(n) => n.to_bits(),
+                Value::Native(handle) => OBJ | handle as u64,
+            }
+        }
+    }
+
+    impl From<bool> for Value {
+        fn from(b: bool) -> Value {
+            Value::Bool(b)
+        }
+    }
+
+    impl From<f64> for Value {
+        fn from(n: f64) -> Value {
+            Value::Number(n)
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+mod boxed {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    pub struct Value(u64);
+
+    impl Value {
+        pub fn nil() -> Value {
+            Value(QNAN | TAG_NIL)
+        }
+
+        pub fn is_nil(&self) -> bool {
+            self.0 == QNAN | TAG_NIL
+        }
+
+        pub fn as_bool(&self) -> Option<bool> {
+            match self.0 {
+                bits if bits == QNAN | TAG_FALSE => Some(false),
+                bits if bits == QNAN | TAG_TRUE => Some(true),
+                _ => None,
+            }
+        }
+
+        pub fn as_number(&self) -> Option<f64> {
+            if self.0 & QNAN != QNAN {
+                Some(f64::from_bits(self.0))
+            } else {
+                None
+            }
+        }
+
+        pub fn native(handle: u32) -> Value {
+            Value(OBJ | handle as u64)
+        }
+
+        pub fn as_native(&self) -> Option<u32> {
+            if self.0 & OBJ == OBJ {
+                Some(self.0 as u32)
+            } else {
+                None
+            }
+        }
+
+        pub fn to_bits(self) -> u64 {
+            self.0
+        }
+    }
+
+    impl From<bool> for Value {
+        fn from(b: bool) -> Value {
+            Value(QNAN | if b { TAG_TRUE } else { TAG_FALSE })
+        }
+    }
+
+    impl From<f64> for Value {
+        fn from(n: f64) -> Value {
+            Value(n.to_bits())
+        }
+    }
+
+    // numbers compare as f64, so NaN != NaN
+    impl PartialEq for Value {
+        fn eq(&self, other: &Value) -> bool {
+            match (self.as_number(), other.as_number()) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.0 == other.0,
+            }
+        }
+    }
+
+    impl fmt::Debug for Value {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.as_number() {
+                Some(n) => write!(f, "Number({:?})", n),
+                None => write!(f, "{}", self),
+            }
+        }
+    }
+}
+
+impl Value {
+    // nil & false, everything else is true
+    pub fn is_falsey(&self) -> bool {
+        self.is_nil() || self.as_bool() == Some(false)
+    }
+}
+
+impl Default for Value {
+    fn default() -> Value {
+        Value::nil()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(n) = self.as_number() {
+            write!(f, "{}", n)
+        } else if let Some(b) = self.as_bool() {
+            write!(f, "{}", b)
+        } else if self.as_native().is_some() {
+            write!(f, "<native fn>")
+        } else {
+            write!(f, "nil")
+        }
+    }
+}
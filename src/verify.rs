@@ -0,0 +1,129 @@
+// Bytecode verifier, run before executing chunks that didn't come
+// from our compiler. After it passes the VM can't index out of the
+// code, the constants or the stack.
+//
+// Code is checked by simulating the stack depth of every op once,
+// ops reached again (by jumps, when there are any) must agree on
+// the depth. Instruction boundaries are found by a linear decode
+// first, jumps must land on them.
+
+use std::fmt;
+
+use crate::chunk::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    // op offset
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[offset {:04}] {}", self.offset, self.message)
+    }
+}
+
+pub fn verify(bytes: &Chunk) -> Result<(), VerifyError> {
+    let error = |offset, message: &str| {
+        Err(VerifyError {
+            offset,
+            message: String::from(message),
+        })
+    };
+    if bytes.count != bytes.code.len() {
+        return error(0, "Op count doesn't match code length.");
+    }
+    // runs start at 0 and go up within the code
+    let starts = bytes.lines.iter().map(|run| run.start);
+    let ordered = starts.clone().zip(starts.skip(1)).all(|(a, b)| a < b);
+    let first = bytes.lines.first().map(|run| run.start);
+    let last = bytes.lines.last().map_or(0, |run| run.start);
+    if !ordered || (bytes.count > 0 && (first != Some(0) || last >= bytes.count)) {
+        return error(0, "Line table doesn't cover the code.");
+    }
+
+    // decode once, linearly, to find instruction boundaries
+    let mut decoded: Vec<Option<Instruction>> = vec![None; bytes.count];
+    let mut offset = 0;
+    while offset < bytes.count {
+        let ins = match bytes.decode(offset) {
+            Some(ins) => ins,
+            None if Op::from_byte(bytes.code[offset]).is_some() => {
+                return error(offset, "Missing operand.")
+            }
+            None => return error(offset, "Unknown op."),
+        };
+        decoded[offset] = Some(ins);
+        offset += ins.size();
+    }
+
+    // depth before each op, None until reached
+    let mut depths: Vec<Option<usize>> = vec![None; bytes.count];
+    let mut pending = vec![(0, 0)];
+
+    while let Some((offset, depth)) = pending.pop() {
+        if offset >= bytes.count {
+            return error(offset, "Code ends without return.");
+        }
+        let ins = match decoded[offset] {
+            Some(ins) => ins,
+            None => return error(offset, "Jump into the middle of an instruction."),
+        };
+        match depths[offset] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => return error(offset, "Inconsistent stack depth."),
+            None => depths[offset] = Some(depth),
+        }
+
+        if ins
+            .constant()
+            .is_some_and(|idx| idx >= bytes.constants.len())
+        {
+            return error(offset, "Constant index out of range.");
+        }
+        if ins.name().is_some_and(|idx| idx >= bytes.names.len()) {
+            return error(offset, "Name index out of range.");
+        }
+
+        // (values popped, values pushed)
+        let (pops, pushes) = match ins {
+            Instruction::Constant(_) | Instruction::ConstantLong(_) => (0, 1),
+            Instruction::Negate
+            | Instruction::Not
+            | Instruction::AddConstant(_)
+            | Instruction::SubtractConstant(_)
+            | Instruction::MultiplyConstant(_)
+            | Instruction::DivideConstant(_) => (1, 1),
+            Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::Equal
+            | Instruction::Greater
+            | Instruction::GreaterEqual
+            | Instruction::Less
+            | Instruction::LessEqual => (2, 1),
+            // ends this path, with the value of the script
+            Instruction::Return => {
+                if depth < 1 {
+                    return error(offset, "Stack underflow.");
+                }
+                continue;
+            }
+            Instruction::Nil
+            | Instruction::True
+            | Instruction::False
+            | Instruction::GetGlobal(_)
+            | Instruction::GetGlobalLong(_) => (0, 1),
+            // callee & arguments for the result
+            Instruction::Call(argc) => (argc as usize + 1, 1),
+            Instruction::Print | Instruction::Pop => (1, 0),
+        };
+        if depth < pops {
+            return error(offset, "Stack underflow.");
+        }
+        pending.push((offset + ins.size(), depth - pops + pushes));
+    }
+    Ok(())
+}
@@ -1,8 +1,36 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
 use crate::chunk::*;
+#[cfg(feature = "trace")]
 use crate::debug::Disassembler;
-use crate::DEBUG_TRACE;
+use crate::native::*;
+use crate::verify::{verify, VerifyError};
+
+// default limits, as in clox
+pub const FRAMES_MAX: usize = 64;
+pub const STACK_MAX: usize = FRAMES_MAX * 256;
+// the stack is allocated up front, larger limits are clamped
+// to this, which with slot 0 fills a power of two
+pub const STACK_CAP: usize = (1 << 20) - 1;
+
+// bounds of the VM, going past them is a "Stack overflow." error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    // values on the stack, at most `STACK_CAP`
+    pub stack: usize,
+    // call frames, the script takes one
+    pub frames: usize,
+}
 
-const STACK_SIZE: usize = 4098;
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            stack: STACK_MAX,
+            frames: FRAMES_MAX,
+        }
+    }
+}
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -12,78 +40,527 @@ pub enum InterpretResult {
     RuntimeError,
 }
 
+// what to do after the debugger is done with an op
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    Continue,
+    Terminate,
+}
+
+// a debugger can pause execution between instructions
+pub trait DebugHook {
+    // called before executing the op at `vm.ip()`
+    fn before_op(&mut self, vm: &VirtualMachine, bytes: &Chunk) -> Resume;
+
+    // program output, the debugger may own stdout
+    fn print(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    // a runtime error & its trace
+    fn error(&mut self, text: &str) {
+        eprintln!("{}", text);
+    }
+}
+
+// keeps the last line printed instead & counts ops, for tests
+// and benchmarks; ops are only counted by `debug`
 #[derive(Debug, Default)]
+pub struct Capture {
+    pub output: String,
+    pub ops: u64,
+}
+
+impl DebugHook for Capture {
+    fn before_op(&mut self, _: &VirtualMachine, _: &Chunk) -> Resume {
+        self.ops += 1;
+        Resume::Continue
+    }
+
+    fn print(&mut self, text: &str) {
+        self.output = String::from(text);
+    }
+}
+
+#[derive(Debug)]
 pub struct VirtualMachine {
     // debug
+    #[cfg(feature = "trace")]
     debugger: Disassembler,
     // current op offset
     ip: usize,
-    // store unfinished op
-    stack: Vec<Value>,
+    limits: Limits,
+    // store unfinished op, values are in slots 1..=sp and
+    // slot 0 is never read, so an empty stack has a top to cache
+    //
+    // pushes check the limit, pops are masked instead: the
+    // verifier proves chunks never pop an empty stack, the mask
+    // only keeps a bad chunk from reading outside the buffer
+    stack: Box<[Value]>,
+    mask: usize,
+    sp: usize,
+    globals: HashMap<String, Value>,
+    // native values are indices in here
+    natives: Vec<Native>,
+    // reused for native arguments
+    args: Vec<Value>,
+    // outcome of the last run
+    result: Option<Value>,
+    error: Option<RuntimeError>,
+    // natives running, a run they start is nested in the current one
+    depth: usize,
+}
+
+impl Default for VirtualMachine {
+    fn default() -> VirtualMachine {
+        VirtualMachine::new()
+    }
 }
 
 impl VirtualMachine {
     pub fn new() -> VirtualMachine {
-        VirtualMachine {
+        VirtualMachine::with_limits(Limits::default())
+    }
+
+    pub fn with_limits(mut limits: Limits) -> VirtualMachine {
+        limits.stack = limits.stack.min(STACK_CAP);
+        // slot 0 & the limit, rounded up so slots can be masked
+        let size = (limits.stack + 1).next_power_of_two();
+        let mut vm = VirtualMachine {
+            #[cfg(feature = "trace")]
             debugger: Disassembler::new(),
             ip: 0,
-            stack: Vec::with_capacity(STACK_SIZE),
-        }
+            limits,
+            stack: vec![Value::nil(); size].into_boxed_slice(),
+            mask: size - 1,
+            sp: 0,
+            globals: HashMap::new(),
+            natives: Vec::new(),
+            args: Vec::new(),
+            result: None,
+            error: None,
+            depth: 0,
+        };
+        vm.define_native("clock", 0, clock);
+        vm
+    }
+
+    // a global `name` calling `function` with `arity` arguments,
+    // replaces any global of that name
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        self.define(name, arity, Rc::new(function));
+    }
+
+    // like `define_native` for a typed closure, e.g.
+    //
+    //   vm.define_fn("add", |a: f64, b: f64| a + b);
+    //
+    // arguments of the wrong type are runtime errors
+    pub fn define_fn<Args, F: IntoNative<Args>>(&mut self, name: &str, function: F) {
+        self.define(name, F::arity(), function.into_native());
+    }
+
+    fn define(&mut self, name: &str, arity: u8, function: NativeBox) {
+        let native = Native {
+            name: String::from(name),
+            arity,
+            function,
+        };
+        // a native defined again takes its old slot
+        let old = self
+            .global(name)
+            .filter(|val| self.native(*val).is_some_and(|old| old.name == name));
+        let handle = match old.and_then(|val| val.as_native()) {
+            Some(handle) => {
+                self.natives[handle as usize] = native;
+                handle
+            }
+            _ => {
+                self.natives.push(native);
+                self.natives.len() as u32 - 1
+            }
+        };
+        self.globals
+            .insert(String::from(name), Value::native(handle));
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).copied()
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(String::from(name), value);
+    }
+
+    pub fn globals(&self) -> &HashMap<String, Value> {
+        &self.globals
+    }
+
+    // a VM with a copy of the globals & the same natives, for a
+    // debugger to evaluate in while this one is paused
+    pub fn fork(&self) -> VirtualMachine {
+        let mut vm = VirtualMachine::with_limits(self.limits);
+        vm.globals = self.globals.clone();
+        vm.natives = self.natives.clone();
+        vm
+    }
+
+    // the native a value refers to, if it's one of ours
+    pub fn native(&self, value: Value) -> Option<&Native> {
+        self.natives.get(value.as_native()? as usize)
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
     }
 
     // another possible way is to take the ownership,
     //
     // pub fn interpret(mut self, ref bytes: Chunk) { }
 
+    // the value of the script is printed unless it's nil,
+    // errors go to stderr
+    //
+    // these run any chunk, so they verify it first and a bad one
+    // is a compile error instead of a panic in the loop
     pub fn interpret(mut self, bytes: &Chunk) -> InterpretResult {
+        if let Err(err) = verify(bytes) {
+            return invalid(err, None);
+        }
+        self.reset();
+        let status = self.run::<false>(bytes, None);
+        self.report(status, None)
+    }
+
+    // output goes to `hook`, which isn't called between ops
+    pub fn interpret_with(mut self, bytes: &Chunk, hook: &mut dyn DebugHook) -> InterpretResult {
+        if let Err(err) = verify(bytes) {
+            return invalid(err, Some(hook));
+        }
+        self.rerun(bytes, hook)
+    }
+
+    // `interpret_with` for a verified chunk, keeping the VM so
+    // benchmarks can build one before timing runs
+    pub(crate) fn rerun(&mut self, bytes: &Chunk, hook: &mut dyn DebugHook) -> InterpretResult {
+        self.reset();
+        let status = self.run::<false>(bytes, Some(&mut *hook));
+        self.report(status, Some(hook))
+    }
+
+    // run under a debugger, tracing is off
+    pub fn debug(mut self, bytes: &Chunk, hook: &mut dyn DebugHook) -> InterpretResult {
+        if let Err(err) = verify(bytes) {
+            return invalid(err, Some(hook));
+        }
+        self.reset();
+        let status = self.run::<true>(bytes, Some(&mut *hook));
+        self.report(status, Some(hook))
+    }
+
+    // run & return the result, globals are kept for the next run
+    //
+    // a native may call this, its run goes on above the values
+    // of the one that called the native, which then resumes
+    pub fn execute(&mut self, bytes: &Chunk) -> Result<Value, RuntimeError> {
+        if let Err(err) = verify(bytes) {
+            let msg = format!("Invalid bytecode: {}", err);
+            return Err(RuntimeError::new(&msg));
+        }
+        self.execute_verified(bytes)
+    }
+
+    // `execute` for a chunk from the compiler or verified before
+    pub(crate) fn execute_verified(&mut self, bytes: &Chunk) -> Result<Value, RuntimeError> {
+        let (ip, sp) = (self.ip, self.sp);
+        if self.depth == 0 {
+            self.reset();
+        } else {
+            self.ip = 0;
+        }
+        let outcome = match self.run::<false>(bytes, None) {
+            InterpretResult::OK => Ok(self.result.take().unwrap_or_default()),
+            _ => Err(self.error.take().expect("runtime error is set")),
+        };
+        if self.depth > 0 {
+            self.ip = ip;
+            self.sp = sp;
+        }
+        outcome
+    }
+
+    fn reset(&mut self) {
         self.ip = 0;
-        self.run(bytes)
+        self.sp = 0;
+        self.result = None;
+        self.error = None;
+    }
+
+    // print the result or the error of a run, to `hook` if any
+    fn report(
+        &mut self,
+        status: InterpretResult,
+        mut hook: Option<&mut dyn DebugHook>,
+    ) -> InterpretResult {
+        // nil when the script doesn't end in an expression
+        if let Some(val) = self.result.take().filter(|val| !val.is_nil()) {
+            match hook.as_mut() {
+                Some(hook) => hook.print(&val.to_string()),
+                None => println!("{}", val),
+            }
+        }
+        if let Some(err) = self.error.take() {
+            match hook {
+                Some(hook) => hook.error(&err.to_string()),
+                None => eprintln!("{}", err),
+            }
+        }
+        status
+    }
+
+    // offset of next op
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack[1..=self.sp]
+    }
+
+    // call depth, the script & a frame per run nested in it
+    // by a native, until functions are supported
+    pub fn frame_count(&self) -> usize {
+        self.depth + 1
     }
 
-    fn run(&mut self, bytes: &Chunk) -> InterpretResult {
-        while self.ip < bytes.count {
-            // print trace if debug is on
-            if DEBUG_TRACE {
-                // print stack
-                for val in self.stack.iter() {
-                    print!("[{}]", val);
+    // ip, sp & the top of stack are kept in locals, and only
+    // written back where a hook or the trace looks at the VM
+    //
+    // `HOOKED` is a constant so the plain loop has no hook checks
+    fn run<const HOOKED: bool>(
+        &mut self,
+        bytes: &Chunk,
+        mut hook: Option<&mut dyn DebugHook>,
+    ) -> InterpretResult {
+        let code = &bytes.code[..];
+        let mut ip = self.ip;
+        let mut sp = self.sp;
+        let mut top = self.stack[sp & self.mask];
+
+        if self.frame_count() > self.limits.frames {
+            return self.runtime_error(bytes, ip, "Stack overflow.");
+        }
+        while ip < code.len() {
+            if HOOKED {
+                self.ip = ip;
+                self.sp = sp;
+                self.stack[sp & self.mask] = top;
+                if let Some(hook) = hook.as_mut() {
+                    if let Resume::Terminate = hook.before_op(self, bytes) {
+                        return InterpretResult::OK;
+                    }
                 }
-                println!();
-                // print op
-                self.debugger.disassemble_op(bytes);
             }
-            // execute instruction
-            let op = bytes.code[self.ip];
+            #[cfg(feature = "trace")]
+            if !HOOKED {
+                self.sp = sp;
+                self.stack[sp & self.mask] = top;
+                self.trace(bytes, ip);
+            }
+            // execute instruction, ip moves past it first
+            let start = ip;
+            let op = match Op::from_byte(code[ip]) {
+                Some(op) => op,
+                None => panic!("Invalid op at {}.", ip),
+            };
+            ip += 1;
+
             match op {
                 Op::Return => {
-                    let val = self.stack.pop().unwrap();
-
-                    println!("{}", val);
+                    self.result = Some(top);
+                    self.ip = ip;
+                    self.sp = sp.wrapping_sub(1);
                     return InterpretResult::OK;
                 }
                 Op::Constant => {
-                    let (_, val) = bytes.get_constant(self.ip);
-                    self.stack.push(val);
-                    self.ip += 2;
+                    let idx = code[ip] as usize;
+                    ip += 1;
+                    push!(self, bytes, start, sp, top, bytes.constants[idx]);
+                }
+                Op::ConstantLong => {
+                    let idx = bytes.read_u24(ip).expect("Missing operand.");
+                    ip += 3;
+                    push!(self, bytes, start, sp, top, bytes.constants[idx]);
                 }
                 Op::Negate => {
-                    unary_op!(self, -);
+                    unary_op!(self, bytes, start, top, -);
                 }
                 Op::Add => {
-                    bin_op!(self, +);
+                    bin_op!(self, bytes, start, sp, top, +);
                 }
                 Op::Subtract => {
-                    bin_op!(self, -);
+                    bin_op!(self, bytes, start, sp, top, -);
                 }
                 Op::Multiply => {
-                    bin_op!(self, *);
+                    bin_op!(self, bytes, start, sp, top, *);
                 }
                 Op::Divide => {
-                    bin_op!(self, /);
+                    bin_op!(self, bytes, start, sp, top, /);
+                }
+                Op::Nil => {
+                    push!(self, bytes, start, sp, top, Value::nil());
+                }
+                Op::AddConstant => {
+                    let idx = code[ip] as usize;
+                    ip += 1;
+                    bin_op_const!(self, bytes, start, top, idx, +);
+                }
+                Op::SubtractConstant => {
+                    let idx = code[ip] as usize;
+                    ip += 1;
+                    bin_op_const!(self, bytes, start, top, idx, -);
+                }
+                Op::MultiplyConstant => {
+                    let idx = code[ip] as usize;
+                    ip += 1;
+                    bin_op_const!(self, bytes, start, top, idx, *);
+                }
+                Op::DivideConstant => {
+                    let idx = code[ip] as usize;
+                    ip += 1;
+                    bin_op_const!(self, bytes, start, top, idx, /);
+                }
+                Op::GetGlobal => {
+                    let idx = code[ip] as usize;
+                    ip += 1;
+                    get_global!(self, bytes, start, sp, top, idx);
+                }
+                Op::GetGlobalLong => {
+                    let idx = bytes.read_u24(ip).expect("Missing operand.");
+                    ip += 3;
+                    get_global!(self, bytes, start, sp, top, idx);
+                }
+                Op::Call => {
+                    let argc = code[ip] as usize;
+                    ip += 1;
+                    // the callee may look at the VM
+                    self.ip = ip;
+                    self.sp = sp;
+                    self.stack[sp & self.mask] = top;
+
+                    match self.call(argc) {
+                        Ok(val) => {
+                            sp = sp.wrapping_sub(argc);
+                            top = val;
+                        }
+                        Err(err) => return self.runtime_error(bytes, start, &err.message),
+                    }
+                }
+                Op::Print => {
+                    let text = top.to_string();
+                    sp = sp.wrapping_sub(1);
+                    top = self.stack[sp & self.mask];
+                    match hook.as_mut() {
+                        Some(hook) => hook.print(&text),
+                        None => println!("{}", text),
+                    }
+                }
+                Op::Pop => {
+                    sp = sp.wrapping_sub(1);
+                    top = self.stack[sp & self.mask];
+                }
+                Op::True => {
+                    push!(self, bytes, start, sp, top, Value::from(true));
+                }
+                Op::False => {
+                    push!(self, bytes, start, sp, top, Value::from(false));
+                }
+                Op::Not => {
+                    top = Value::from(top.is_falsey());
+                }
+                Op::Equal => {
+                    sp = sp.wrapping_sub(1);
+                    let a = self.stack[sp & self.mask];
+                    top = Value::from(a == top);
+                }
+                Op::Greater => {
+                    bin_op!(self, bytes, start, sp, top, >);
+                }
+                Op::GreaterEqual => {
+                    bin_op!(self, bytes, start, sp, top, >=);
+                }
+                Op::Less => {
+                    bin_op!(self, bytes, start, sp, top, <);
+                }
+                Op::LessEqual => {
+                    bin_op!(self, bytes, start, sp, top, <=);
                 }
-                _ => self.ip += 1,
             }
         }
         panic!("Chunk has no return.");
     }
+
+    // call the callee below `argc` arguments on top of the stack,
+    // which is written back
+    fn call(&mut self, argc: usize) -> Result<Value, RuntimeError> {
+        let base = self.sp.wrapping_sub(argc);
+        let callee = self.stack[base & self.mask];
+
+        let mut args = std::mem::take(&mut self.args);
+        args.clear();
+        for i in 1..=argc {
+            args.push(self.stack[(base + i) & self.mask]);
+        }
+        self.depth += 1;
+        let result = self.call_value(callee, &args);
+        self.depth -= 1;
+        self.args = args;
+        result
+    }
+
+    // call `callee` with `args`, from a script or the host
+    pub fn call_value(&mut self, callee: Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let (arity, function) = match self.native(callee) {
+            Some(native) => (native.arity as usize, Rc::clone(&native.function)),
+            None => return Err(RuntimeError::new("Can only call functions and classes.")),
+        };
+        if args.len() != arity {
+            let msg = format!("Expected {} arguments but got {}.", arity, args.len());
+            return Err(RuntimeError::new(&msg));
+        }
+        function(self, args)
+    }
+
+    // print stack & the next op, built with `--features trace`
+    #[cfg(feature = "trace")]
+    fn trace(&mut self, bytes: &Chunk, ip: usize) {
+        for val in self.stack().iter() {
+            print!("[{}]", val);
+        }
+        println!();
+        self.debugger.seek(ip);
+        self.debugger.disassemble_op(bytes);
+    }
+
+    // keep the error with the line of the op at `offset`
+    fn runtime_error(&mut self, bytes: &Chunk, offset: usize, msg: &str) -> InterpretResult {
+        let mut err = RuntimeError::new(msg);
+        err.trace
+            .push(format!("[line {}] in script", bytes.get_line(offset)));
+        self.error = Some(err);
+
+        self.ip = offset;
+        self.sp = 0;
+        InterpretResult::RuntimeError
+    }
+}
+
+// a chunk that fails verification, reported like a compile error
+fn invalid(err: VerifyError, hook: Option<&mut dyn DebugHook>) -> InterpretResult {
+    let msg = format!("Invalid bytecode: {}", err);
+    match hook {
+        Some(hook) => hook.error(&msg),
+        None => eprintln!("{}", msg),
+    }
+    InterpretResult::CompileError
 }
@@ -0,0 +1,172 @@
+use serde_json::{json, Value};
+
+fn frame(msgs: &[Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for msg in msgs.iter() {
+        let body = msg.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).bytes());
+    }
+    input
+}
+
+fn unframe(output: &[u8]) -> Vec<Value> {
+    let output = String::from_utf8(output.to_vec()).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|msg| !msg.is_empty())
+        .map(|msg| serde_json::from_str(msg.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+fn request(seq: u64, command: &str, args: Value) -> Value {
+    json!({ "seq": seq, "type": "request", "command": command, "arguments": args })
+}
+
+// (type, event or command) of each message
+fn kinds(msgs: &[Value]) -> Vec<String> {
+    msgs.iter()
+        .map(|msg| match msg["type"].as_str().unwrap() {
+            "event" => format!("event {}", msg["event"].as_str().unwrap()),
+            _ => format!("response {}", msg["command"].as_str().unwrap()),
+        })
+        .collect()
+}
+
+#[test]
+fn test_dap_session() {
+    let program = std::env::temp_dir().join("rlox_dap_session.lox");
+    std::fs::write(&program, "1 +\n2 *\n3").unwrap();
+    let program = program.to_str().unwrap();
+
+    let input = frame(&[
+        request(1, "initialize", json!({ "adapterID": "rlox" })),
+        request(2, "launch", json!({ "program": program })),
+        request(
+            3,
+            "setBreakpoints",
+            json!({ "source": { "path": program }, "breakpoints": [{ "line": 2 }, { "line": 9 }] }),
+        ),
+        request(4, "configurationDone", json!({})),
+        // stopped at line 2
+        request(5, "stackTrace", json!({ "threadId": 1 })),
+        request(6, "variables", json!({ "variablesReference": 1 })),
+        request(7, "next", json!({ "threadId": 1 })),
+        // stopped at line 3
+        request(8, "stackTrace", json!({ "threadId": 1 })),
+        request(9, "variables", json!({ "variablesReference": 1 })),
+        request(10, "continue", json!({ "threadId": 1 })),
+        request(11, "disconnect", json!({})),
+    ]);
+    let mut output = Vec::new();
+    rlox::dap::serve(&input[..], &mut output).unwrap();
+
+    let msgs = unframe(&output);
+    assert_eq!(
+        kinds(&msgs),
+        vec![
+            "response initialize",
+            "event initialized",
+            "response launch",
+            "response setBreakpoints",
+            "response configurationDone",
+            "event stopped",
+            "response stackTrace",
+            "response variables",
+            "response next",
+            "event stopped",
+            "response stackTrace",
+            "response variables",
+            "response continue",
+            "event output",
+            "event exited",
+            "event terminated",
+            "response disconnect",
+        ]
+    );
+    assert!(msgs.iter().all(|msg| msg["success"] != false));
+
+    let bps = &msgs[3]["body"]["breakpoints"];
+    assert_eq!(bps[0], json!({ "verified": true, "line": 2 }));
+    assert_eq!(bps[1]["verified"], false);
+
+    assert_eq!(msgs[5]["body"]["reason"], "breakpoint");
+    assert_eq!(msgs[6]["body"]["stackFrames"][0]["line"], 2);
+    assert_eq!(msgs[7]["body"]["variables"][0]["value"], "1");
+
+    assert_eq!(msgs[9]["body"]["reason"], "step");
+    assert_eq!(msgs[10]["body"]["stackFrames"][0]["line"], 3);
+    let stack: Vec<&Value> = msgs[11]["body"]["variables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|var| &var["value"])
+        .collect();
+    assert_eq!(stack, vec!["1", "2"]);
+
+    assert_eq!(msgs[13]["body"]["output"], "7\n");
+    assert_eq!(msgs[14]["body"]["exitCode"], 0);
+}
+
+#[test]
+fn test_dap_stop_on_entry() {
+    let program = std::env::temp_dir().join("rlox_dap_entry.lox");
+    std::fs::write(&program, "-1").unwrap();
+    let program = program.to_str().unwrap();
+
+    let input = frame(&[
+        request(1, "initialize", json!({})),
+        request(
+            2,
+            "launch",
+            json!({ "program": program, "stopOnEntry": true }),
+        ),
+        request(3, "configurationDone", json!({})),
+        request(4, "scopes", json!({ "frameId": 0 })),
+        request(5, "variables", json!({ "variablesReference": 2 })),
+        request(6, "terminate", json!({})),
+    ]);
+    let mut output = Vec::new();
+    rlox::dap::serve(&input[..], &mut output).unwrap();
+
+    let msgs = unframe(&output);
+    let stopped = msgs.iter().find(|msg| msg["event"] == "stopped").unwrap();
+    assert_eq!(stopped["body"]["reason"], "entry");
+
+    let scopes = msgs.iter().find(|msg| msg["command"] == "scopes").unwrap();
+    assert_eq!(scopes["body"]["scopes"][1]["name"], "Globals");
+    let vars = msgs
+        .iter()
+        .find(|msg| msg["command"] == "variables")
+        .unwrap();
+    assert_eq!(
+        vars["body"]["variables"],
+        json!([{ "name": "clock", "value": "<native fn>", "variablesReference": 0 }])
+    );
+    // terminated before printing
+    assert!(msgs.iter().all(|msg| msg["event"] != "output"));
+}
+
+#[test]
+fn test_dap_runtime_error() {
+    let program = std::env::temp_dir().join("rlox_dap_error.lox");
+    std::fs::write(&program, "1;\n-clock").unwrap();
+    let program = program.to_str().unwrap();
+
+    let input = frame(&[
+        request(1, "initialize", json!({})),
+        request(2, "launch", json!({ "program": program })),
+        request(3, "configurationDone", json!({})),
+        request(4, "disconnect", json!({})),
+    ]);
+    let mut output = Vec::new();
+    rlox::dap::serve(&input[..], &mut output).unwrap();
+
+    let msgs = unframe(&output);
+    let error = msgs.iter().find(|msg| msg["event"] == "output").unwrap();
+    assert_eq!(
+        error["body"],
+        json!({ "category": "stderr", "output": "Operand must be a number.\n[line 2] in script\n" })
+    );
+    let exited = msgs.iter().find(|msg| msg["event"] == "exited").unwrap();
+    assert_eq!(exited["body"]["exitCode"], 70);
+}
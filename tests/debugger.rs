@@ -0,0 +1,71 @@
+use rlox::debugger::debug;
+use rlox::vm::InterpretResult;
+
+fn session(source: &str, commands: &str) -> (InterpretResult, String) {
+    let mut output = Vec::new();
+    let status = debug(source, commands.as_bytes(), &mut output);
+    (status, String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn test_debugger_session() {
+    let commands = "break 2\ncontinue\nstack\nprint (1 + 2) * 4\nnext\ndisasm\ncontinue\n";
+    let (status, output) = session("1 +\n2 *\n3", commands);
+
+    assert_eq!(status, InterpretResult::OK);
+    let output: Vec<&str> = output.split("(rlox) ").collect();
+    assert_eq!(
+        output,
+        vec![
+            "   1 | 1 +\n",
+            "Breakpoint at line 2.\n",
+            "   2 | 2 *\n",
+            "#0 script at line 2\n  [0] 1\n",
+            "12\n",
+            "   3 | 3\n",
+            "   0000     1   OP_CONST  0000 1\n   0002     2   OP_CONST  0001 2\n=> 0004     3   OP_CONST  0002 3\n   0006     |   OP_MUL\n   0007     |   OP_ADD\n   0008     |   OP_RETURN\n",
+            "7\n",
+        ]
+    );
+}
+
+#[test]
+fn test_debugger_quit() {
+    let (status, output) = session("-1", "b 7\nfoo\nlocals\nquit\n");
+
+    assert_eq!(status, InterpretResult::OK);
+    assert!(output.contains("No code at or after line 7."));
+    assert!(output.contains("Unknown command 'foo', try 'help'."));
+    // until there are local variables
+    assert!(output.contains("Unknown command 'locals', try 'help'."));
+    // stopped before printing
+    assert!(output.ends_with("(rlox) "));
+}
+
+#[test]
+fn test_debugger_print() {
+    use rlox::debugger::debug_with;
+    use rlox::vm::VirtualMachine;
+    use rlox::Value;
+
+    let mut vm = VirtualMachine::new();
+    vm.define_fn("double", |n: f64| n * 2.0);
+    vm.set_global("ten", Value::from(10.0));
+
+    let commands = "print double(ten) + 1\nprint 1 +\nprint -double\nprint 1; 2\nquit\n";
+    let mut output = Vec::new();
+    debug_with(vm, "double(1)", commands.as_bytes(), &mut output);
+
+    let output = String::from_utf8(output).unwrap();
+    let output: Vec<&str> = output.split("(rlox) ").skip(1).collect();
+    assert_eq!(
+        output,
+        vec![
+            "21\n",
+            "[line 1:4] Error at end: Expect expression.\n",
+            "Operand must be a number.\n",
+            "Expect an expression.\n",
+            "",
+        ]
+    );
+}
@@ -1,7 +1,10 @@
-use rlox;
+use std::borrow::Cow;
 
+use rlox::ast::*;
 use rlox::chunk::*;
-use rlox::vm::VirtualMachine;
+use rlox::lexer::{reconstruct, Scanner, TokenType, TriviaKind};
+use rlox::vm::{Capture, InterpretResult, VirtualMachine};
+use rlox::LoxError;
 
 #[test]
 fn it_works() {
@@ -12,19 +15,13 @@ fn it_works() {
 fn test_interpreter() {
     let mut chk = Chunk::new();
 
-    let c1 = chk.add_constant(1.2);
-    chk.write(Op::Constant, 0);
-    chk.write(c1, 0);
+    chk.write_constant(1.2, 0);
 
-    let c2 = chk.add_constant(3.4);
-    chk.write(Op::Constant, 1);
-    chk.write(c2, 1);
+    chk.write_constant(3.4, 1);
 
     chk.write(Op::Add, 2);
 
-    let c3 = chk.add_constant(5.6);
-    chk.write(Op::Constant, 2);
-    chk.write(c3, 2);
+    chk.write_constant(5.6, 2);
 
     chk.write(Op::Divide, 3);
 
@@ -34,3 +31,431 @@ fn test_interpreter() {
     let vm = VirtualMachine::new();
     vm.interpret(&chk);
 }
+
+#[test]
+fn test_tokenize() {
+    let tokens = rlox::tokenize("var s = \"lox\"; // comment\nprint s;");
+    let types: Vec<TokenType> = tokens.iter().map(|t| t.tt).collect();
+
+    assert_eq!(
+        types,
+        vec![
+            TokenType::Var,
+            TokenType::Identifier,
+            TokenType::Equal,
+            TokenType::Str,
+            TokenType::Semicolon,
+            TokenType::Print,
+            TokenType::Identifier,
+            TokenType::Semicolon,
+            TokenType::Eof,
+        ]
+    );
+    // lexemes are borrowed from source
+    assert!(matches!(tokens[1].value, Cow::Borrowed("s")));
+    assert!(matches!(tokens[3].value, Cow::Borrowed("lox")));
+    assert_eq!(tokens[6].line, 2);
+}
+
+#[test]
+fn test_trivia_round_trip() {
+    let files = std::fs::read_dir("tests/lox").unwrap();
+
+    for file in files {
+        let path = file.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let tokens: Vec<_> = Scanner::with_trivia(&source).collect();
+
+        assert_eq!(reconstruct(&tokens), source, "{:?}", path);
+    }
+}
+
+#[test]
+fn test_trivia() {
+    let tokens: Vec<_> = Scanner::with_trivia("a // b\n  c").collect();
+    let kinds: Vec<TriviaKind> = tokens[1].leading.iter().map(|t| t.kind).collect();
+
+    assert_eq!(
+        kinds,
+        vec![
+            TriviaKind::Whitespace,
+            TriviaKind::Comment,
+            TriviaKind::Newline,
+            TriviaKind::Whitespace,
+        ]
+    );
+    assert_eq!(tokens[1].leading[1].text, "// b");
+    assert_eq!(tokens[1].lexeme, "c");
+    // no trivia unless asked
+    assert!(rlox::tokenize("a // b\n  c")[1].leading.is_empty());
+}
+
+#[test]
+fn test_parse_corpus() {
+    let files = std::fs::read_dir("tests/lox").unwrap();
+
+    for file in files {
+        let path = file.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let result = rlox::parse(&source);
+
+        if path.ends_with("errors.lox") {
+            let errors = result.unwrap_err();
+            let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+
+            assert_eq!(
+                messages,
+                vec![
+                    "Unexpected character '@'",
+                    "Unterminated string starting at line 2"
+                ]
+            );
+        } else {
+            assert!(result.is_ok(), "{:?}: {:?}", path, result);
+        }
+    }
+}
+
+#[test]
+fn test_front_ends_agree() {
+    // what the VM can't run yet must be the only difference
+    let unsupported = |errors: &[String]| {
+        errors.len() == 1 && errors[0].ends_with("Not supported by the VM yet.")
+    };
+    let check = |name: &str, source: &str| {
+        let compiled = rlox::compile(source);
+        let parsed = rlox::parse(source);
+        match compiled {
+            Ok(_) => assert!(parsed.is_ok(), "{} runs but doesn't parse", name),
+            Err(LoxError::Compile(errors)) if parsed.is_ok() => {
+                assert!(unsupported(&errors), "{} parses but {:?}", name, errors)
+            }
+            Err(_) => {}
+        }
+        rlox::compile(source).is_ok()
+    };
+
+    let mut runnable = Vec::new();
+    for dir in ["tests/lox", "benches/lox"].iter() {
+        for file in std::fs::read_dir(dir).unwrap() {
+            let path = file.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            if check(&path.display().to_string(), &source) {
+                runnable.push(path);
+            }
+        }
+    }
+    assert!(runnable.iter().any(|p| p.starts_with("tests/lox")));
+    assert!(runnable.iter().any(|p| p.starts_with("benches/lox")));
+    for source in [
+        "",
+        "1 + 2",
+        "print 1;",
+        "1; -2;\n3",
+        "print 1",
+        "1 2",
+        "1; )",
+        "print true;",
+        "nil",
+        "!1",
+        "print \"hi\";",
+        "1 < 2",
+        "1 == 2 != false",
+        "a = 1",
+        "a.b",
+        "this",
+        "1 and 2 or 3",
+        "f(1)",
+        "(1)(2)",
+    ]
+    .iter()
+    {
+        check(source, source);
+    }
+}
+
+#[test]
+fn test_parse_tree() {
+    let source = "var a = -(1 + 2) * b;";
+    let program = rlox::parse(source).unwrap();
+
+    let var = match &program.decls[0] {
+        Decl::Var(var) => var,
+        decl => panic!("expect var, got {:?}", decl),
+    };
+    assert_eq!(var.name.name, "a");
+    assert_eq!(&source[var.span.start..var.span.end], source);
+
+    let init = var.init.as_ref().unwrap();
+    assert_eq!(&source[init.span.start..init.span.end], "-(1 + 2) * b");
+    match &init.kind {
+        ExprKind::Binary { op, left, right } => {
+            assert_eq!(*op, BinaryOp::Multiply);
+            assert!(matches!(
+                left.kind,
+                ExprKind::Unary {
+                    op: UnaryOp::Negate,
+                    ..
+                }
+            ));
+            assert!(matches!(right.kind, ExprKind::Variable(_)));
+        }
+        expr => panic!("expect binary, got {:?}", expr),
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    // parser recovers and reports both
+    let errors = rlox::parse("var = 1;\nprint 1 +;\n1 = 2;").unwrap_err();
+    let lines: Vec<(usize, &str)> = errors
+        .iter()
+        .map(|e| (e.line, e.message.as_str()))
+        .collect();
+
+    assert_eq!(
+        lines,
+        vec![
+            (1, "Expect variable name."),
+            (2, "Expect expression."),
+            (3, "Invalid assignment target."),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_error_at_statement_start() {
+    // the bad token must be skipped, recovery stops after `;`
+    let errors = rlox::parse("1; )").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Expect expression.");
+
+    let errors = rlox::parse("var a = 1; ) print a; }").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(rlox::format::format("1; )").is_err());
+}
+
+#[test]
+fn test_format() {
+    let source = "fun f(a,b){return a-b;}\nif(f(1,-2)>0)print\"yes\";else{print \"no\" ;}\n\n\nvar x=!true;   // trailing\n";
+    let expect = "fun f(a, b) {\n  return a - b;\n}\nif (f(1, -2) > 0) print \"yes\";\nelse {\n  print \"no\";\n}\n\nvar x = !true; // trailing\n";
+
+    assert_eq!(rlox::format::format(source).unwrap(), expect);
+}
+
+#[test]
+fn test_format_idempotent() {
+    let files = std::fs::read_dir("tests/lox").unwrap();
+
+    for file in files {
+        let path = file.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+
+        if let Ok(once) = rlox::format::format(&source) {
+            let twice = rlox::format::format(&once).unwrap();
+            assert_eq!(once, twice, "{:?}", path);
+        }
+    }
+}
+
+#[test]
+fn test_lint() {
+    let source = "var x = 1;
+fun f(a) {
+  var unused = 2;
+  var x = a;
+  if (x == x) return x;
+  return a;
+  print \"never\";
+}
+{
+  var quiet = 1; // lint: allow(W001)
+  var loud = 2; // lint: allow(W003)
+}";
+    let warnings = rlox::lint::lint(source).unwrap();
+    let codes: Vec<(usize, &str)> = warnings.iter().map(|w| (w.line, w.code.unwrap())).collect();
+
+    assert_eq!(
+        codes,
+        vec![
+            (3, "W001"),
+            (4, "W003"),
+            (5, "W004"),
+            (7, "W002"),
+            (11, "W001")
+        ]
+    );
+    assert_eq!(
+        warnings[0].message,
+        "Local variable 'unused' is never used."
+    );
+}
+
+#[test]
+fn test_bytecode() {
+    use rlox::bytecode::*;
+
+    let mut chk = Chunk::new();
+    chk.write_constant(-0.5, 1);
+    chk.write(Op::Negate, 2);
+    chk.write(Op::Return, 2);
+    assert_eq!(chk.add_name("x"), Some(0));
+
+    let data = serialize(&chk);
+    assert!(data.starts_with(MAGIC));
+
+    let mut loaded = deserialize(&data).unwrap();
+    assert_eq!(loaded.code, chk.code);
+    assert_eq!(loaded.get_constant(0), (0, Value::from(-0.5)));
+    assert_eq!(loaded.get_line(2), 2);
+    // loaded names are found again, not added twice
+    assert_eq!(loaded.add_name("y"), Some(1));
+    assert_eq!(loaded.add_name("x"), Some(0));
+
+    let mut corrupt = data.clone();
+    corrupt[8] ^= 1;
+    assert_eq!(deserialize(&corrupt).unwrap_err(), LoadError::Checksum);
+
+    let mut newer = data.clone();
+    newer[4] = VERSION as u8 + 1;
+    assert_eq!(
+        deserialize(&newer).unwrap_err(),
+        LoadError::Version(VERSION + 1)
+    );
+    assert_eq!(
+        deserialize(&data[..data.len() - 1]).unwrap_err(),
+        LoadError::Checksum
+    );
+    assert_eq!(deserialize(b"1 + 2").unwrap_err(), LoadError::NotBytecode);
+}
+
+#[test]
+fn test_verify() {
+    use rlox::verify::verify;
+
+    let message = |chk: &Chunk| verify(chk).unwrap_err().message;
+
+    let mut chk = Chunk::new();
+    chk.write_constant(1.0, 1);
+    chk.write(Op::Negate, 1);
+    assert_eq!(message(&chk), "Code ends without return.");
+
+    chk.write(Op::Return, 1);
+    assert_eq!(verify(&chk), Ok(()));
+
+    let mut chk = Chunk::new();
+    chk.write(Op::Constant, 1);
+    chk.write(3, 1);
+    chk.write(Op::Return, 1);
+    assert_eq!(message(&chk), "Constant index out of range.");
+
+    let mut chk = Chunk::new();
+    chk.write(Op::Return, 1);
+    chk.write(Op::Constant, 1);
+    assert_eq!(message(&chk), "Missing operand.");
+
+    let mut chk = Chunk::new();
+    chk.write_constant(1.0, 1);
+    chk.write(Op::Add, 1);
+    chk.write(Op::Return, 1);
+    let err = verify(&chk).unwrap_err();
+    assert_eq!((err.offset, err.message.as_str()), (2, "Stack underflow."));
+
+    let mut chk = Chunk::new();
+    chk.write(0xff, 1);
+    chk.write(Op::Return, 1);
+    assert_eq!(message(&chk), "Unknown op.");
+
+    // the VM verifies what it runs, a bad chunk is an error
+    let status = VirtualMachine::new().interpret(&chk);
+    assert_eq!(status, InterpretResult::CompileError);
+    let mut output = Capture::default();
+    let status = VirtualMachine::new().debug(&chk, &mut output);
+    assert_eq!((status, output.ops), (InterpretResult::CompileError, 0));
+    assert_eq!(
+        VirtualMachine::new().execute(&chk).unwrap_err().message,
+        "Invalid bytecode: [offset 0000] Unknown op."
+    );
+    assert_eq!(rlox::bench::time(&chk).1, InterpretResult::CompileError);
+}
+
+#[test]
+fn test_bench() {
+    use rlox::bench::*;
+
+    // not folded, every op runs
+    let json = bench("sum", "1 + 2", 0).to_json();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["ops"], 4);
+    assert!(json["gc"].is_null());
+
+    let report = bench("fib", "fun fib(n) {}", 0);
+    assert_eq!(report.status, Status::Unsupported);
+    assert_eq!(bench("typo", "1 +", 0).status, Status::CompileError);
+    assert_eq!(bench("neg", "-nil", 0).status, Status::RuntimeError);
+
+    let table = table(&[report]);
+    assert!(table.lines().nth(1).unwrap().contains("unsupported"));
+}
+
+#[test]
+fn test_embed() {
+    use rlox::{Config, LoxError, Value, Vm};
+
+    let mut vm = Vm::new(Config {
+        opt_level: 2,
+        ..Config::default()
+    });
+    assert_eq!(vm.eval("1 + 2 * 3").unwrap(), Value::from(7.0));
+
+    vm.set_global("x", 4.0);
+    vm.set_global("none", ());
+    assert_eq!(vm.get_global("x"), Some(Value::from(4.0)));
+    assert_eq!(vm.eval("none").unwrap(), Value::nil());
+    assert_eq!(vm.eval("-x").unwrap(), Value::from(-4.0));
+
+    match vm.eval("1 +").unwrap_err() {
+        LoxError::Compile(errors) => {
+            assert_eq!(errors, vec!["[line 1:4] Error at end: Expect expression."])
+        }
+        err => panic!("{:?}", err),
+    }
+    let err = vm.eval("-none").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Operand must be a number.\n[line 1] in script"
+    );
+
+    vm.define_fn("half", |n: f64| n / 2.0);
+    assert_eq!(
+        vm.call("half", &[Value::from(3.0)]).unwrap(),
+        Value::from(1.5)
+    );
+    assert!(matches!(vm.call("half", &[]), Err(LoxError::Runtime(_))));
+    assert!(matches!(vm.call("nope", &[]), Err(LoxError::Runtime(_))));
+
+    let mut chk = rlox::compile("half(1)").unwrap();
+    assert_eq!(vm.run(&chk).unwrap(), Value::from(0.5));
+    chk.truncate(chk.count - 1);
+    assert!(matches!(vm.run(&chk), Err(LoxError::Invalid(_))));
+}
+
+#[test]
+fn test_session() {
+    use rlox::{Value, Vm};
+    use std::cell::Cell;
+
+    // as the REPL runs lines, state survives errors
+    let mut vm = Vm::default();
+    let count = Cell::new(0.0);
+    vm.define_fn("tick", move || {
+        count.set(count.get() + 1.0);
+        count.get()
+    });
+    vm.set_global("step", 10.0);
+
+    assert_eq!(vm.eval("tick()").unwrap(), Value::from(1.0));
+    assert!(vm.eval("-tick").is_err());
+    assert!(vm.eval("tick(").is_err());
+    assert_eq!(vm.eval("tick() * step").unwrap(), Value::from(20.0));
+}
@@ -0,0 +1,143 @@
+use serde_json::{json, Value};
+
+const SOURCE: &str = "fun add(a, b) {
+  return a + b;
+}
+var sum = add(1, 2);
+{
+  var unused = sum;
+}
+";
+
+fn frame(msgs: &[Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for msg in msgs.iter() {
+        let body = msg.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).bytes());
+    }
+    input
+}
+
+fn unframe(output: &[u8]) -> Vec<Value> {
+    let output = String::from_utf8(output.to_vec()).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|msg| !msg.is_empty())
+        .map(|msg| serde_json::from_str(msg.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+#[test]
+fn test_lsp_session() {
+    let doc = json!({ "uri": "file:///add.lox" });
+    let input = frame(&[
+        request(1, "initialize", json!({})),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///add.lox", "text": SOURCE } },
+        }),
+        // `add` in `var sum = add(1, 2);`
+        request(
+            2,
+            "textDocument/definition",
+            json!({ "textDocument": doc, "position": { "line": 3, "character": 11 } }),
+        ),
+        // `b` in `return a + b;`
+        request(
+            3,
+            "textDocument/hover",
+            json!({ "textDocument": doc, "position": { "line": 1, "character": 13 } }),
+        ),
+        request(
+            4,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": doc }),
+        ),
+        request(
+            5,
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": doc }),
+        ),
+        request(6, "shutdown", Value::Null),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ]);
+    let mut output = Vec::new();
+    rlox::lsp::serve(&input[..], &mut output).unwrap();
+
+    let msgs = unframe(&output);
+    assert_eq!(msgs.len(), 7);
+    assert_eq!(msgs[0]["result"]["capabilities"]["hoverProvider"], true);
+
+    let diagnostics = &msgs[1]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["code"], "W001");
+    assert_eq!(diagnostics[0]["severity"], 2);
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({ "line": 5, "character": 6 })
+    );
+    // from the compiler, after the warnings
+    assert_eq!(diagnostics[1]["message"], "Not supported by the VM yet.");
+    assert_eq!(
+        diagnostics[1]["range"],
+        json!({
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 3 },
+        })
+    );
+
+    assert_eq!(
+        msgs[2]["result"]["range"],
+        json!({
+            "start": { "line": 0, "character": 4 },
+            "end": { "line": 0, "character": 7 },
+        })
+    );
+    assert_eq!(
+        msgs[3]["result"]["contents"]["value"],
+        "```lox\n(parameter) b\n```"
+    );
+
+    let symbols = msgs[4]["result"].as_array().unwrap();
+    let names: Vec<&str> = symbols
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["add", "sum"]);
+
+    // `fun` keyword, then `add` function
+    let data = msgs[5]["result"]["data"].as_array().unwrap();
+    assert_eq!(data[..10], [0, 0, 3, 0, 0, 0, 4, 3, 5, 0]);
+
+    assert_eq!(msgs[6]["result"], Value::Null);
+}
+
+#[test]
+fn test_lsp_syntax_error() {
+    let input = frame(&[json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": "file:///bad.lox", "text": "print 1 +;" } },
+    })]);
+    let mut output = Vec::new();
+    rlox::lsp::serve(&input[..], &mut output).unwrap();
+
+    let msgs = unframe(&output);
+    let diagnostic = &msgs[0]["params"]["diagnostics"][0];
+    assert_eq!(diagnostic["severity"], 1);
+    assert_eq!(diagnostic["message"], "Expect expression.");
+}
+
+#[test]
+fn test_lsp_length_limit() {
+    // not allocated, the peer can't be trusted with the size
+    let input = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+    let mut output = Vec::new();
+    let err = rlox::lsp::serve(input.as_bytes(), &mut output).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(output.is_empty());
+}